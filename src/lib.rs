@@ -1,12 +1,30 @@
+#![allow(
+    clippy::needless_arbitrary_self_type,
+    clippy::redundant_field_names,
+    clippy::needless_return,
+    clippy::redundant_pattern_matching,
+    clippy::borrowed_box,
+)]
+
+use std::any::Any;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::error;
+use std::fmt;
+use std::io;
 
+pub use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq, Clone)]
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
 pub enum Value {
     Null,
     Bool(bool),
     Int(i64),
+    #[serde(serialize_with = "serialize_finite")]
     Float(f64),
     String(String),
     Array(Vec<Value>),
@@ -14,18 +32,75 @@ pub enum Value {
 }
 
 
-#[derive(Debug)]
+// JSON has no NaN or infinity, and writing them as null would reload them
+// as Value::Null
+fn serialize_finite<S: serde::Serializer>(f: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    if !f.is_finite() {
+        return Err(serde::ser::Error::custom(format!("{} can't be written as JSON", f)))
+    }
+    serializer.serialize_f64(*f)
+}
+
+
+impl Value {
+    /// A total order over values, for sorting. Null sorts first, then Bool,
+    /// numbers, String, Array and Object. Int and Float compare by value, an
+    /// Int first when they are equal, and NaN after every other number.
+    /// Arrays compare element by element, Objects by their sorted entries.
+    pub fn total_cmp(self: &Self, other: &Value) -> Ordering {
+        let rank = |v: &Value| match v {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Int(_) | Value::Float(_) => 2,
+            Value::String(_) => 3,
+            Value::Array(_) => 4,
+            Value::Object(_) => 5
+        };
+
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Float(a), Value::Float(b)) if a.is_nan() || b.is_nan() => a.is_nan().cmp(&b.is_nan()),
+            (Value::Float(a), Value::Int(_)) if a.is_nan() => Ordering::Greater,
+            (Value::Int(_), Value::Float(b)) if b.is_nan() => Ordering::Less,
+            (Value::Int(_), Value::Int(_)) | (Value::Float(_), Value::Float(_)) => compare_values(self, other).unwrap(),
+            (Value::Int(_), Value::Float(_)) => compare_values(self, other).unwrap().then(Ordering::Less),
+            (Value::Float(_), Value::Int(_)) => compare_values(self, other).unwrap().then(Ordering::Greater),
+            (Value::String(a), Value::String(b)) => a.cmp(b),
+            (Value::Array(a), Value::Array(b)) => {
+                a.iter().zip(b.iter()).map(|(x, y)| x.total_cmp(y)).find(|o| *o != Ordering::Equal)
+                    .unwrap_or_else(|| a.len().cmp(&b.len()))
+            },
+            (Value::Object(a), Value::Object(b)) => {
+                let mut a: Vec<_> = a.iter().collect();
+                let mut b: Vec<_> = b.iter().collect();
+                a.sort_by(|x, y| x.0.cmp(y.0));
+                b.sort_by(|x, y| x.0.cmp(y.0));
+
+                a.iter().zip(b.iter()).map(|(x, y)| x.0.cmp(y.0).then_with(|| x.1.total_cmp(y.1))).find(|o| *o != Ordering::Equal)
+                    .unwrap_or_else(|| a.len().cmp(&b.len()))
+            },
+            _ => rank(self).cmp(&rank(other))
+        }
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Vertex {
+    #[serde(default)]
     pub properties: HashMap<String, Value>,
+    #[serde(skip)]
     pub e_in: Vec<u64>,
+    #[serde(skip)]
     pub e_out: Vec<u64>,
     pub id: u64
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Edge {
     pub label: String,
+    #[serde(default)]
     pub properties: HashMap<String, Value>,
     pub v_in: u64,
     pub v_out: u64,
@@ -37,18 +112,81 @@ pub struct Edge {
 pub struct Graph {
     pub autoid: u64,
     pub vertices: HashMap<u64, Box<Vertex>>,
-    pub edges: HashMap<u64, Box<Edge>>
+    pub edges: HashMap<u64, Box<Edge>>,
+    indexes: HashMap<String, PropertyIndex>,
+    transformers: Vec<Transformer>,
+    aliases: HashMap<String, Alias>,
+    ordered: bool
+}
+
+
+#[derive(Debug)]
+pub enum DagobaError {
+    VertexNotFound(u64),
+    EdgeNotFound(u64),
+    DanglingEdge(u64),
+    DuplicateId(u64),
+    Json(serde_json::Error),
+    Query(QueryError),
+}
+
+
+impl fmt::Display for DagobaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DagobaError::VertexNotFound(id) => write!(f, "vertex {} not found", id),
+            DagobaError::EdgeNotFound(id) => write!(f, "edge {} not found", id),
+            DagobaError::DanglingEdge(id) => write!(f, "edge {} is not linked to both of its vertices", id),
+            DagobaError::DuplicateId(id) => write!(f, "id {} is used more than once", id),
+            DagobaError::Json(e) => write!(f, "json error: {}", e),
+            DagobaError::Query(e) => write!(f, "query error: {}", e),
+        }
+    }
+}
+
+
+impl error::Error for DagobaError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            DagobaError::Json(e) => Some(e),
+            DagobaError::Query(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+
+impl From<serde_json::Error> for DagobaError {
+    fn from(e: serde_json::Error) -> DagobaError {
+        DagobaError::Json(e)
+    }
+}
+
+
+impl From<QueryError> for DagobaError {
+    fn from(e: QueryError) -> DagobaError {
+        DagobaError::Query(e)
+    }
 }
 
 
 impl Graph {
 
     pub fn new() -> Graph {
-        Graph{
+        let mut graph = Graph{
             autoid: 0,
             vertices: HashMap::new(),
-            edges: HashMap::new()
-        }
+            edges: HashMap::new(),
+            indexes: HashMap::new(),
+            transformers: Vec::new(),
+            aliases: HashMap::new(),
+            ordered: false
+        };
+
+        graph.add_transformer(100, fold_vertex_filters);
+        graph.add_transformer(90, merge_takes);
+
+        graph
     }
     
     
@@ -58,9 +196,15 @@ impl Graph {
     }
 
 
-    pub fn add_vertex(self: &mut Self, properties: HashMap<String, Value>) -> Result<u64, String> {
+    pub fn add_vertex(self: &mut Self, properties: HashMap<String, Value>) -> Result<u64, DagobaError> {
         
         let id = self.next_id();
+
+        for (k, v) in &properties {
+            if let Some(index) = self.indexes.get_mut(k) {
+                index.insert(v, id);
+            }
+        }
         
         self.vertices.insert(id, Box::new(Vertex {
             properties: properties,
@@ -73,19 +217,44 @@ impl Graph {
     }
     
     
-    pub fn add_edge(self: &mut Self, v_out: u64,  v_in: u64, label: String, properties: HashMap<String, Value>) -> Result<u64, String> {
+    pub fn add_edge(self: &mut Self, v_out: u64,  v_in: u64, label: String, properties: HashMap<String, Value>) -> Result<u64, DagobaError> {
         
+        self.check_endpoints(v_out, v_in)?;
+
+        Ok(self.link_edge(v_out, v_in, label, properties))
+    }
+
+
+    /// Adds every edge or none of them: all endpoints are checked before the
+    /// graph is touched. Returns the new edge ids in input order.
+    pub fn add_edges(self: &mut Self, edges: Vec<(u64, u64, String, HashMap<String, Value>)>) -> Result<Vec<u64>, DagobaError> {
+
+        for (v_out, v_in, _, _) in &edges {
+            self.check_endpoints(*v_out, *v_in)?;
+        }
+
+        Ok(edges.into_iter().map(|(v_out, v_in, label, properties)| self.link_edge(v_out, v_in, label, properties)).collect())
+    }
+
+
+    fn check_endpoints(self: &Self, v_out: u64, v_in: u64) -> Result<(), DagobaError> {
+        if !self.vertices.contains_key(&v_out) {
+            return Err(DagobaError::VertexNotFound(v_out));
+        }
+        if !self.vertices.contains_key(&v_in) {
+            return Err(DagobaError::VertexNotFound(v_in));
+        }
+        Ok(())
+    }
+
+
+    // both endpoints must already be known to exist
+    fn link_edge(self: &mut Self, v_out: u64,  v_in: u64, label: String, properties: HashMap<String, Value>) -> u64 {
+
         let id = self.next_id();
-              
-        println!("{} {} {}", v_in, v_out, id);
-        
-        let vertex_in =  self.vertices.get_mut(&v_in).ok_or("Vertex Not Found")?;
-        
-        vertex_in.e_in.push(id);
-        
-        let vertex_out =  self.vertices.get_mut(&v_out).ok_or("Vertex Not Found")?;
 
-        vertex_out.e_out.push(id);
+        self.vertices.get_mut(&v_in).unwrap().e_in.push(id);
+        self.vertices.get_mut(&v_out).unwrap().e_out.push(id);
 
         self.edges.insert(id, Box::new(Edge {
             label: label,
@@ -94,18 +263,17 @@ impl Graph {
             v_out: v_out,
             id: id
         }));
-        
-        
-        Ok(id)
+
+        id
     }
     
     
-    pub fn remove_vertex(self: &mut Self, id:u64) -> Result<(), String> {
+    pub fn remove_vertex(self: &mut Self, id:u64) -> Result<(), DagobaError> {
     
         let mut edges_to_remove = Vec::new();
         
         {
-            let v = self.vertices.get(&id).ok_or("Vertex Not Found")?;
+            let v = self.vertices.get(&id).ok_or(DagobaError::VertexNotFound(id))?;
             
             for e in &v.e_in {
                 edges_to_remove.push(*e);
@@ -120,22 +288,28 @@ impl Graph {
             self.remove_edge(e)?;
         }
         
-        self.vertices.remove(&id);
+        if let Some(v) = self.vertices.remove(&id) {
+            for (k, value) in &v.properties {
+                if let Some(index) = self.indexes.get_mut(k) {
+                    index.remove(value, id);
+                }
+            }
+        }
         
         Ok(())
     }
     
     
-    pub fn remove_edge(self: &mut Self, id:u64) -> Result<(), String> {
+    pub fn remove_edge(self: &mut Self, id:u64) -> Result<(), DagobaError> {
     
-        let e = self.edges.get(&id).ok_or("Edge Not Found")?;
+        let e = self.edges.get(&id).ok_or(DagobaError::EdgeNotFound(id))?;
         
-        let v_in = self.vertices.get_mut(&e.v_in).ok_or("Vertex Not Found")?;
-        let e_in_idx = v_in.e_in.iter().rposition(|&x| x == id).ok_or("Edge Not Found")?;
+        let v_in = self.vertices.get_mut(&e.v_in).ok_or(DagobaError::DanglingEdge(id))?;
+        let e_in_idx = v_in.e_in.iter().rposition(|&x| x == id).ok_or(DagobaError::DanglingEdge(id))?;
         v_in.e_in.remove(e_in_idx);
         
-        let v_out = self.vertices.get_mut(&e.v_out).ok_or("Vertex Not Found")?;
-        let e_out_idx = v_out.e_out.iter().rposition(|&x| x == id).ok_or("Edge Not Found")?;
+        let v_out = self.vertices.get_mut(&e.v_out).ok_or(DagobaError::DanglingEdge(id))?;
+        let e_out_idx = v_out.e_out.iter().rposition(|&x| x == id).ok_or(DagobaError::DanglingEdge(id))?;
         v_out.e_out.remove(e_out_idx);
         
         self.edges.remove(&id);
@@ -144,698 +318,3012 @@ impl Graph {
     }
     
     
-    pub fn get_verticies(self: &Self, ids: &Vec<u64>) -> Vec<&Box<Vertex>> {
-        ids.iter().filter_map( |id| self.vertices.get(&id) ).collect()
+    pub fn set_vertex_property(self: &mut Self, id: u64, key: String, value: Value) -> Result<Option<Value>, DagobaError> {
+
+        let v = self.vertices.get_mut(&id).ok_or(DagobaError::VertexNotFound(id))?;
+
+        if let Some(index) = self.indexes.get_mut(&key) {
+            if let Some(old) = v.properties.get(&key) {
+                index.remove(old, id);
+            }
+            index.insert(&value, id);
+        }
+
+        Ok(v.properties.insert(key, value))
+    }
+
+
+    pub fn remove_vertex_property(self: &mut Self, id: u64, key: &str) -> Result<Option<Value>, DagobaError> {
+
+        let v = self.vertices.get_mut(&id).ok_or(DagobaError::VertexNotFound(id))?;
+
+        let previous = v.properties.remove(key);
+
+        if let (Some(index), Some(old)) = (self.indexes.get_mut(key), &previous) {
+            index.remove(old, id);
+        }
+
+        Ok(previous)
+    }
+
+
+    /// Sets every given property, returning the previous values of the keys
+    /// that were overwritten.
+    pub fn merge_vertex_properties(self: &mut Self, id: u64, properties: HashMap<String, Value>) -> Result<HashMap<String, Value>, DagobaError> {
+
+        if !self.vertices.contains_key(&id) {
+            return Err(DagobaError::VertexNotFound(id));
+        }
+
+        let mut previous = HashMap::new();
+
+        for (k, v) in properties {
+            if let Some(old) = self.set_vertex_property(id, k.clone(), v)? {
+                previous.insert(k, old);
+            }
+        }
+
+        Ok(previous)
+    }
+
+
+    pub fn set_edge_property(self: &mut Self, id: u64, key: String, value: Value) -> Result<Option<Value>, DagobaError> {
+        let e = self.edges.get_mut(&id).ok_or(DagobaError::EdgeNotFound(id))?;
+        Ok(e.properties.insert(key, value))
+    }
+
+
+    pub fn remove_edge_property(self: &mut Self, id: u64, key: &str) -> Result<Option<Value>, DagobaError> {
+        let e = self.edges.get_mut(&id).ok_or(DagobaError::EdgeNotFound(id))?;
+        Ok(e.properties.remove(key))
+    }
+
+
+    pub fn merge_edge_properties(self: &mut Self, id: u64, properties: HashMap<String, Value>) -> Result<HashMap<String, Value>, DagobaError> {
+
+        let e = self.edges.get_mut(&id).ok_or(DagobaError::EdgeNotFound(id))?;
+
+        let mut previous = HashMap::new();
+
+        for (k, v) in properties {
+            if let Some(old) = e.properties.insert(k.clone(), v) {
+                previous.insert(k, old);
+            }
+        }
+
+        Ok(previous)
+    }
+
+
+    pub fn get_verticies(self: &Self, ids: &[u64]) -> Vec<&Box<Vertex>> {
+        ids.iter().filter_map( |id| self.vertices.get(id) ).collect()
     }
     
     
     pub fn get_vertex(self: &Self, id:&u64) -> Option<&Box<Vertex>> {
-        self.vertices.get(&id)
+        self.vertices.get(id)
     }
     
     
     pub fn search_verticies(self: &Self, filter: &VertexFilter) -> Vec<u64>  {
         if let VertexFilter::Props(f) = filter {
+            if let Some(ids) = self.index_search(f) {
+                return ids
+            }
             return self.vertices.values().filter( move |v| properties_filter(&v.properties, f) ).map(|v| v.id).collect()
         } else if let VertexFilter::Id(id) = filter {
             return vec![*id];
         } else if let VertexFilter::Ids(ids) = filter {
             return ids.clone();
-        } else if let VertexFilter::Fn(f) = filter {
-            return self.vertices.values().filter( |x| f(x) ).map(|v| v.id).collect()
+        } else if let VertexFilter::None = filter {
+            return self.vertices.keys().copied().collect();
+        } else if let Some(ids) = self.index_candidates(filter) {
+            return ids.iter().filter(|id| self.vertices.get(id).is_some_and(|v| filter_vertex(v, filter))).copied().collect()
         } else {
-            return self.vertices.keys().map(|k| *k).collect();
+            return self.vertices.values().filter( |x| filter_vertex(x, filter) ).map(|v| v.id).collect()
         }
     }
     
     
-    pub fn get_out_edges(self: &Self, vertex_id: &u64) -> Vec<&Box<Edge>> {
-        let vertex = self.vertices.get(&vertex_id).unwrap();
-        vertex.e_out.iter().filter_map( move |edge_id| self.edges.get(edge_id) ).collect()
+    pub fn get_out_edges(self: &Self, vertex_id: &u64) -> Result<Vec<&Box<Edge>>, DagobaError> {
+        let vertex = self.vertices.get(vertex_id).ok_or(DagobaError::VertexNotFound(*vertex_id))?;
+        Ok(vertex.e_out.iter().filter_map( move |edge_id| self.edges.get(edge_id) ).collect())
     }
     
     
-    pub fn get_in_edges(self: &Self, vertex_id: &u64) -> Vec<&Box<Edge>> {
-        let vertex = self.vertices.get(&vertex_id).unwrap();
-        vertex.e_in.iter().filter_map( move |edge_id| self.edges.get(edge_id) ).collect()
+    pub fn get_in_edges(self: &Self, vertex_id: &u64) -> Result<Vec<&Box<Edge>>, DagobaError> {
+        let vertex = self.vertices.get(vertex_id).ok_or(DagobaError::VertexNotFound(*vertex_id))?;
+        Ok(vertex.e_in.iter().filter_map( move |edge_id| self.edges.get(edge_id) ).collect())
     }
-    
-    // TODO implements to/from JSON string
-}
 
 
-pub enum EdgeFilter {
-    None,
-    Label(String),
-    Labels(Vec<String>),
-    Props(HashMap<String, Value>)
-}
+    /// Indexes vertices by the value of `key` so `VertexFilter::Props` lookups
+    /// on it avoid a full scan. The index follows the vertex and property
+    /// methods on `Graph`; changes made directly through the `vertices` field
+    /// bypass it.
+    pub fn create_index(self: &mut Self, key: &str) {
 
-pub enum VertexFilter {
-    None,
-    Id(u64),
-    Ids(Vec<u64>),
-    Props(HashMap<String, Value>),
-    Fn(Box<dyn Fn(&Vertex) -> bool>)
-}
+        if self.indexes.contains_key(key) {
+            return
+        }
 
+        let mut index = PropertyIndex::default();
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum QueryResult {
-    None,
-    Value(Value),
-    Vertex(u64),
-}
+        for v in self.vertices.values() {
+            if let Some(value) = v.properties.get(key) {
+                index.insert(value, v.id);
+            }
+        }
 
-impl QueryResult {
-    pub fn as_value(&self) -> &Value {
-        match self {
-            QueryResult::Value(v) => v,
-            _ => panic!("QueryResult is not Value"),
-        }    
+        self.indexes.insert(key.to_string(), index);
     }
 
-    pub fn as_vertex(&self) -> &u64 {
-        match self {
-            QueryResult::Vertex(v) => v,
-            _ => panic!("QueryResult is not Vertex"),
-        }    
+
+    pub fn drop_index(self: &mut Self, key: &str) -> bool {
+        self.indexes.remove(key).is_some()
     }
-}
 
-pub struct Query<'a> {
-    pub graph: &'a Graph,
-    pub program: Vec<Box<dyn Pipe + 'a>>
-}
 
-impl<'a> Query<'a> {
-    
-    pub fn new(graph: &'a Graph, filter: VertexFilter) -> Query<'a> {
-        Query {
-            graph: graph,
-            program: vec![Box::new(VertexPipe::new(graph, filter))]
+    pub fn has_index(self: &Self, key: &str) -> bool {
+        self.indexes.contains_key(key)
+    }
+
+
+    // Some(keys) when every key of the filter is indexed, so a match can be
+    // decided with index_contains alone
+    fn index_keys(self: &Self, f: &HashMap<String, Value>) -> Option<Vec<(String, Option<IndexKey>)>> {
+        if f.keys().all(|k| self.indexes.contains_key(k)) {
+            Some(f.iter().map(|(k, v)| (k.clone(), IndexKey::new(v))).collect())
+        } else {
+            None
         }
     }
 
-    pub fn run(self: &mut Self) -> Vec<QueryResult> {
 
-        // TRANSFORM PROGRAM
+    fn index_contains(self: &Self, key: &str, value: &Option<IndexKey>, id: u64) -> bool {
+        match (self.indexes.get(key), value) {
+            (Some(index), Some(k)) => index.entries.get(k).is_some_and(|ids| ids.contains(&id)),
+            _ => false
+        }
+    }
 
-        let max = self.program.len() as i32 - 1;
 
-        let mut maybe_gremlin = MaybeGremlin::False;
-        let mut results = Vec::new();
-        let mut done:i32 = -1;
-        let mut pc = max;
-    
-        while done < max {
-            
-            let step = &mut self.program[pc as usize];
-            maybe_gremlin = step.run(match maybe_gremlin { MaybeGremlin::Gremlin(g) => Some(g), _ => None });
+    // Resolves a props filter through the smallest matching index bucket, or
+    // None when none of its keys are indexed
+    fn index_search(self: &Self, f: &HashMap<String, Value>) -> Option<Vec<u64>> {
 
-            println!("maybe_gremlin {:?}", maybe_gremlin);
+        let mut best: Option<&HashSet<u64>> = None;
 
-            if let MaybeGremlin::Pull = maybe_gremlin {
-                maybe_gremlin = MaybeGremlin::False;
-                if pc-1 > done {
-                    pc -= 1;
-                    continue;
-                } else {
-                    done = pc;
+        for (k, v) in f {
+            let index = match self.indexes.get(k) {
+                Some(index) => index,
+                None => continue
+            };
+
+            match index.get(v) {
+                None => return Some(Vec::new()),
+                Some(ids) => if best.is_none_or(|b| ids.len() < b.len()) {
+                    best = Some(ids);
                 }
             }
+        }
 
-            if let MaybeGremlin::Done = maybe_gremlin {
-                maybe_gremlin = MaybeGremlin::False;
-                done = pc;
-            }
+        best.map(|ids| ids.iter().filter(|id| self.vertices.get(id).is_some_and(|v| properties_filter(&v.properties, f))).copied().collect())
+    }
 
-            pc += 1;
 
-            if pc > max {
-                if let MaybeGremlin::Gremlin(r) = maybe_gremlin {
-                    results.push(r);
+    // A superset of the vertices matching filter, worked out from ids and
+    // indexes alone, or None when that would take a scan. And narrows to the
+    // smallest known branch, Or needs every branch to be known.
+    fn index_candidates(self: &Self, filter: &VertexFilter) -> Option<HashSet<u64>> {
+        match filter {
+            VertexFilter::Id(id) => Some(std::iter::once(*id).collect()),
+            VertexFilter::Ids(ids) => Some(ids.iter().copied().collect()),
+            VertexFilter::Props(f) => {
+                let mut best: Option<HashSet<u64>> = None;
+
+                for (k, v) in f {
+                    if let Some(index) = self.indexes.get(k) {
+                        let ids = index.get(v).cloned().unwrap_or_default();
+                        if best.as_ref().is_none_or(|b| ids.len() < b.len()) {
+                            best = Some(ids);
+                        }
+                    }
                 }
-                maybe_gremlin = MaybeGremlin::False;
-                pc -= 1;
-            }
-        }
 
-        results.iter().map(|g| {
-                if g.result.is_some() { 
-                    QueryResult::Value(g.result.as_ref().unwrap().clone())
-                } else if g.vertex.is_some() { 
-                    QueryResult::Vertex(g.vertex.unwrap()) 
-                } else {
-                    QueryResult::None
+                best
+            },
+            VertexFilter::And(fs) => fs.iter().filter_map(|f| self.index_candidates(f)).min_by_key(|ids| ids.len()),
+            VertexFilter::Or(fs) => {
+                let mut ids = HashSet::new();
+                for f in fs {
+                    ids.extend(self.index_candidates(f)?);
                 }
-            }
-        ).collect()
-    }
-    
-    // // Pipetypes
-    pub fn vertex(self: &mut Self, filter: VertexFilter) -> &mut Self {
-        self.program.push(Box::new(VertexPipe::new(&self.graph, filter)));
-        self
-    }
-    
-    pub fn r#in(self: &mut Self, filter: EdgeFilter) -> &mut Self {
-        self.program.push(Box::new(SimpleTraversalPipe::new(&self.graph, SimpleTraversalDir::In, filter)));
-        self
+                Some(ids)
+            },
+            _ => None
+        }
     }
-    
-    pub fn out(self: &mut Self, filter: EdgeFilter) -> &mut Self {
-        self.program.push(Box::new(SimpleTraversalPipe::new(&self.graph, SimpleTraversalDir::Out, filter)));
-        self
+
+
+    /// Makes vertex steps produce vertices, and traversals their edges, in
+    /// ascending id order rather than whatever order the maps give, so the
+    /// same query on the same graph always gives the same results.
+    pub fn set_ordered(self: &mut Self, ordered: bool) {
+        self.ordered = ordered;
     }
-    
-    pub fn both(self: &mut Self, filter: EdgeFilter) -> &mut Self {
-        self.program.push(Box::new(SimpleTraversalPipe::new(&self.graph, SimpleTraversalDir::Both, filter)));
-        self
+
+
+    /// Registers a rewrite of query programs, applied once before a query
+    /// first runs. Transformers run highest priority first, in registration
+    /// order among equals. The built-in rules are registered at priority 100
+    /// (folding `filter` steps into a preceding `vertex` step) and 90
+    /// (merging consecutive `take` steps).
+    pub fn add_transformer<F>(self: &mut Self, priority: i32, transformer: F) where F: Fn(&mut Program) + 'static {
+        let i = self.transformers.iter().position(|t| t.priority < priority).unwrap_or(self.transformers.len());
+        self.transformers.insert(i, Transformer { priority: priority, f: Box::new(transformer) });
     }
 
-    pub fn property(self: &mut Self, property: String) -> &mut Self {
-        self.program.push(Box::new(PropertyPipe::new(&self.graph, property)));
-        self
+
+    /// Removes every transformer, the built-in ones included.
+    pub fn clear_transformers(self: &mut Self) {
+        self.transformers.clear();
     }
 
-    pub fn unique(self: &mut Self) -> &mut Self {
-        self.program.push(Box::new(UniquePipe::new()));
-        self
+
+    /// Names a sequence of query steps so it can be reused with `Query::alias`.
+    /// Aliases may use other aliases; redefining a name replaces it.
+    pub fn add_alias<F>(self: &mut Self, name: &str, steps: F) where F: for<'q, 'g> Fn(&'q mut Query<'g>) -> &'q mut Query<'g> + 'static {
+        self.aliases.insert(name.to_string(), Alias { f: Box::new(steps) });
     }
 
-    pub fn filter(self: &mut Self, filter:VertexFilter) -> &mut Self {
-        self.program.push(Box::new(FilterPipe::new(&self.graph, filter)));
-        self
+
+    pub fn remove_alias(self: &mut Self, name: &str) -> bool {
+        self.aliases.remove(name).is_some()
     }
 
-    pub fn take(self: &mut Self, take:i64) -> &mut Self {
-        self.program.push(Box::new(TakePipe::new(take)));
-        self
+
+    pub fn has_alias(self: &Self, name: &str) -> bool {
+        self.aliases.contains_key(name)
     }
 
-    pub fn r#as(self: &mut Self, label:String) -> &mut Self {
-        self.program.push(Box::new(AsPipe::new(label)));
-        self
+
+    fn transform(self: &Self, program: &mut Program) {
+        for t in &self.transformers {
+            (t.f)(program);
+        }
     }
 
-    pub fn back(self: &mut Self, label:String) -> &mut Self {
-        self.program.push(Box::new(BackPipe::new(label)));
-        self
+
+    pub fn to_json(self: &Self) -> Result<String, DagobaError> {
+        Ok(serde_json::to_string(&self.to_record())?)
     }
 
-    pub fn except(self: &mut Self, label:String) -> &mut Self {
-        self.program.push(Box::new(ExceptPipe::new(label)));
-        self
+
+    pub fn to_writer<W: io::Write>(self: &Self, writer: W) -> Result<(), DagobaError> {
+        Ok(serde_json::to_writer(writer, &self.to_record())?)
     }
 
-    pub fn merge(self: &mut Self, labels:Vec<String>) -> &mut Self {
-        self.program.push(Box::new(MergePipe::new(labels)));
-        self
+
+    pub fn from_json(json: &str) -> Result<Graph, DagobaError> {
+        let record = serde_json::from_str(json)?;
+        Graph::from_record(record)
     }
-}
 
 
-pub trait Pipe {
-    fn run(self: &mut Self, gremlin: Option<Gremlin>) -> MaybeGremlin;
-}
+    pub fn from_reader<R: io::Read>(reader: R) -> Result<Graph, DagobaError> {
+        let record = serde_json::from_reader(reader)?;
+        Graph::from_record(record)
+    }
 
 
-pub struct VertexPipe<'a> {
-    init: bool,
-    vertices: Vec<u64>,
-    graph: &'a Graph,
-    filter: VertexFilter,
-}
+    fn to_record(self: &Self) -> GraphRecordRef<'_> {
+        let mut vertices: Vec<&Vertex> = self.vertices.values().map(|v| v.as_ref()).collect();
+        vertices.sort_by_key(|v| v.id);
 
+        let mut edges: Vec<&Edge> = self.edges.values().map(|e| e.as_ref()).collect();
+        edges.sort_by_key(|e| e.id);
 
-impl<'a> VertexPipe<'a> {
-    fn new(graph: &'a Graph, filter: VertexFilter) -> VertexPipe<'a> {
-        VertexPipe {
-            init: false,
-            vertices: Vec::new(),
-            graph: graph,
-            filter: filter,
+        let mut indexes: Vec<&str> = self.indexes.keys().map(|k| k.as_str()).collect();
+        indexes.sort();
+
+        GraphRecordRef {
+            autoid: self.autoid,
+            vertices: vertices,
+            edges: edges,
+            indexes: indexes
         }
     }
-}
 
 
-impl<'a> Pipe for VertexPipe<'a> {
-    
-    fn run(self: &mut Self, gremlin: Option<Gremlin>) -> MaybeGremlin {
-        
-        if !self.init {
-            self.vertices.extend(self.graph.search_verticies(&self.filter));
-            self.init = true;
-        } 
-        
-        if self.vertices.is_empty() {
-            return MaybeGremlin::Done
-        }
+    fn from_record(record: GraphRecord) -> Result<Graph, DagobaError> {
+
+        let mut graph = Graph::new();
+        let mut max_id = 0;
+
+        for v in record.vertices {
+            if graph.vertices.contains_key(&v.id) {
+                return Err(DagobaError::DuplicateId(v.id));
+            }
+            max_id = max_id.max(v.id);
+            graph.vertices.insert(v.id, Box::new(v));
+        }
+
+        // edges are linked in id order so e_in and e_out come back in the
+        // order add_edge originally produced them
+        let mut edges = record.edges;
+        edges.sort_by_key(|e| e.id);
+
+        for e in edges {
+            if graph.vertices.contains_key(&e.id) || graph.edges.contains_key(&e.id) {
+                return Err(DagobaError::DuplicateId(e.id));
+            }
+            if !graph.vertices.contains_key(&e.v_in) || !graph.vertices.contains_key(&e.v_out) {
+                return Err(DagobaError::DanglingEdge(e.id));
+            }
+            max_id = max_id.max(e.id);
+
+            graph.vertices.get_mut(&e.v_in).unwrap().e_in.push(e.id);
+            graph.vertices.get_mut(&e.v_out).unwrap().e_out.push(e.id);
+            graph.edges.insert(e.id, Box::new(e));
+        }
+
+        // never hand out an id that is already taken, even if autoid was edited by hand
+        graph.autoid = record.autoid.max(max_id);
+
+        for key in &record.indexes {
+            graph.create_index(key);
+        }
+
+        Ok(graph)
+    }
+}
+
+
+impl Default for Graph {
+    fn default() -> Graph {
+        Graph::new()
+    }
+}
+
+
+struct Transformer {
+    priority: i32,
+    f: Box<dyn Fn(&mut Program)>
+}
+
+
+impl fmt::Debug for Transformer {
+    fn fmt(self: &Self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Transformer").field("priority", &self.priority).finish()
+    }
+}
+
+
+/// A run of query steps, as used by aliases and branching steps. Build one
+/// with `traversal`.
+pub type Traversal = Box<dyn for<'q, 'g> Fn(&'q mut Query<'g>) -> &'q mut Query<'g>>;
+
+
+pub fn traversal<F>(steps: F) -> Traversal where F: for<'q, 'g> Fn(&'q mut Query<'g>) -> &'q mut Query<'g> + 'static {
+    Box::new(steps)
+}
+
+
+struct Alias {
+    f: Traversal
+}
+
+
+impl fmt::Debug for Alias {
+    fn fmt(self: &Self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Alias")
+    }
+}
+
+
+#[derive(Serialize)]
+struct GraphRecordRef<'a> {
+    autoid: u64,
+    vertices: Vec<&'a Vertex>,
+    edges: Vec<&'a Edge>,
+    indexes: Vec<&'a str>
+}
+
+
+#[derive(Deserialize)]
+struct GraphRecord {
+    autoid: u64,
+    vertices: Vec<Vertex>,
+    edges: Vec<Edge>,
+    #[serde(default)]
+    indexes: Vec<String>
+}
+
+
+#[derive(Debug, Default)]
+struct PropertyIndex {
+    entries: HashMap<IndexKey, HashSet<u64>>
+}
+
+
+impl PropertyIndex {
+    fn insert(self: &mut Self, value: &Value, id: u64) {
+        if let Some(k) = IndexKey::new(value) {
+            self.entries.entry(k).or_default().insert(id);
+        }
+    }
+
+    fn remove(self: &mut Self, value: &Value, id: u64) {
+        if let Some(k) = IndexKey::new(value) {
+            if let Some(ids) = self.entries.get_mut(&k) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.entries.remove(&k);
+                }
+            }
+        }
+    }
+
+    fn get(self: &Self, value: &Value) -> Option<&HashSet<u64>> {
+        IndexKey::new(value).and_then(|k| self.entries.get(&k))
+    }
+}
+
+
+// Hashable stand-in for Value used as an index or dedup key. Two values compare equal
+// exactly when their keys do: floats are keyed by their bits with -0.0
+// folded into 0.0, and values containing NaN (which equal nothing) have no key.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum IndexKey {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(u64),
+    String(String),
+    Array(Vec<IndexKey>),
+    Object(Vec<(String, IndexKey)>),
+}
+
+
+impl IndexKey {
+    fn new(value: &Value) -> Option<IndexKey> {
+        Some(match value {
+            Value::Null => IndexKey::Null,
+            Value::Bool(b) => IndexKey::Bool(*b),
+            Value::Int(i) => IndexKey::Int(*i),
+            Value::Float(f) if f.is_nan() => return None,
+            Value::Float(f) => IndexKey::Float(if *f == 0.0 { 0 } else { f.to_bits() }),
+            Value::String(s) => IndexKey::String(s.clone()),
+            Value::Array(a) => IndexKey::Array(a.iter().map(IndexKey::new).collect::<Option<_>>()?),
+            Value::Object(o) => {
+                let mut entries = o.iter().map(|(k, v)| IndexKey::new(v).map(|v| (k.clone(), v))).collect::<Option<Vec<_>>>()?;
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                IndexKey::Object(entries)
+            }
+        })
+    }
+}
+
+
+pub enum EdgeFilter {
+    None,
+    Label(String),
+    Labels(Vec<String>),
+    Props(HashMap<String, Value>),
+    LabelProps(String, HashMap<String, Value>),
+    Predicates(HashMap<String, Predicate>),
+    And(Vec<EdgeFilter>),
+    Or(Vec<EdgeFilter>),
+    Not(Box<EdgeFilter>),
+    Fn(Box<dyn Fn(&Edge) -> bool>)
+}
+
+pub enum VertexFilter {
+    None,
+    Id(u64),
+    Ids(Vec<u64>),
+    Props(HashMap<String, Value>),
+    Predicates(HashMap<String, Predicate>),
+    And(Vec<VertexFilter>),
+    Or(Vec<VertexFilter>),
+    Not(Box<VertexFilter>),
+    Fn(Box<dyn Fn(&Vertex) -> bool>)
+}
+
+
+/// A test on a single property value. Numeric predicates compare `Int` and
+/// `Float` by value; values that can't be ordered against each other (a
+/// string and a number, say) never match. Everything but `NotExists` fails
+/// when the property is missing.
+pub enum Predicate {
+    Eq(Value),
+    Ne(Value),
+    Gt(Value),
+    Gte(Value),
+    Lt(Value),
+    Lte(Value),
+    /// lower bound inclusive, upper bound exclusive
+    Between(Value, Value),
+    In(Vec<Value>),
+    Exists,
+    NotExists,
+    StartsWith(String),
+    Contains(String),
+    Regex(Regex),
+    ArrayContains(Value),
+}
+
+
+impl Predicate {
+    pub fn test(self: &Self, value: Option<&Value>) -> bool {
+
+        let v = match (self, value) {
+            (Predicate::NotExists, v) => return v.is_none(),
+            (_, None) => return false,
+            (_, Some(v)) => v
+        };
+
+        match self {
+            Predicate::Eq(x) => values_equal(v, x),
+            Predicate::Ne(x) => !values_equal(v, x),
+            Predicate::Gt(x) => compare_values(v, x) == Some(Ordering::Greater),
+            Predicate::Gte(x) => matches!(compare_values(v, x), Some(Ordering::Greater) | Some(Ordering::Equal)),
+            Predicate::Lt(x) => compare_values(v, x) == Some(Ordering::Less),
+            Predicate::Lte(x) => matches!(compare_values(v, x), Some(Ordering::Less) | Some(Ordering::Equal)),
+            Predicate::Between(lo, hi) => {
+                matches!(compare_values(v, lo), Some(Ordering::Greater) | Some(Ordering::Equal))
+                    && compare_values(v, hi) == Some(Ordering::Less)
+            },
+            Predicate::In(xs) => xs.iter().any(|x| values_equal(v, x)),
+            Predicate::Exists => true,
+            Predicate::NotExists => false,
+            Predicate::StartsWith(x) => matches!(v, Value::String(s) if s.starts_with(x.as_str())),
+            Predicate::Contains(x) => matches!(v, Value::String(s) if s.contains(x.as_str())),
+            Predicate::Regex(r) => matches!(v, Value::String(s) if r.is_match(s)),
+            Predicate::ArrayContains(x) => matches!(v, Value::Array(a) if a.iter().any(|e| values_equal(e, x))),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryError {
+    MissingLabel(String),
+    VertexNotFound(u64),
+    EdgeNotFound(u64),
+    UnknownAlias(String),
+    RecursiveAlias(String),
+    Pipe(String),
+    MisplacedStep(String),
+    GroupKeyClash(String),
+}
+
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryError::MissingLabel(label) => write!(f, "nothing labelled '{}'", label),
+            QueryError::VertexNotFound(id) => write!(f, "vertex {} not found", id),
+            QueryError::EdgeNotFound(id) => write!(f, "edge {} not found", id),
+            QueryError::UnknownAlias(name) => write!(f, "no alias named '{}'", name),
+            QueryError::RecursiveAlias(name) => write!(f, "alias '{}' expands to itself", name),
+            QueryError::Pipe(message) => write!(f, "{}", message),
+            QueryError::MisplacedStep(step) => write!(f, "'{}' doesn't follow a step it applies to", step),
+            QueryError::GroupKeyClash(key) => write!(f, "different values share the group key '{}'", key),
+        }
+    }
+}
+
+
+impl error::Error for QueryError {}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryResult {
+    None,
+    Value(Value),
+    Vertex(u64),
+    Edge(u64),
+}
+
+impl QueryResult {
+    pub fn as_value(&self) -> &Value {
+        match self {
+            QueryResult::Value(v) => v,
+            _ => panic!("QueryResult is not Value"),
+        }    
+    }
+
+    pub fn as_vertex(&self) -> &u64 {
+        match self {
+            QueryResult::Vertex(v) => v,
+            _ => panic!("QueryResult is not Vertex"),
+        }    
+    }
+
+    pub fn as_edge(&self) -> &u64 {
+        match self {
+            QueryResult::Edge(e) => e,
+            _ => panic!("QueryResult is not Edge"),
+        }    
+    }
+
+    // vertices and edges become their ids
+    fn into_value(self) -> Value {
+        match self {
+            QueryResult::None => Value::Null,
+            QueryResult::Value(v) => v,
+            QueryResult::Vertex(id) | QueryResult::Edge(id) => Value::Int(id as i64)
+        }
+    }
+}
+
+pub struct Query<'a> {
+    pub graph: &'a Graph,
+    pub program: Program,
+    transformed: bool,
+    expanding: Vec<String>,
+    // kept between runs so a finished stream stays finished
+    machine: Option<Machine>
+}
+
+impl<'a> Query<'a> {
+    
+    pub fn new(graph: &'a Graph, filter: VertexFilter) -> Query<'a> {
+        Query {
+            graph: graph,
+            program: vec![Box::new(VertexPipe::new(filter))],
+            transformed: false,
+            expanding: Vec::new(),
+            machine: None
+        }
+    }
+
+    // a query starting from a single gremlin, for sub-traversals
+    fn seeded(graph: &'a Graph, gremlin: Gremlin) -> Query<'a> {
+        Query {
+            graph: graph,
+            program: vec![Box::new(SeedPipe::new(gremlin))],
+            transformed: false,
+            expanding: Vec::new(),
+            machine: None
+        }
+    }
+
+    /// Runs the query, panicking if it fails. See `try_run`.
+    pub fn run(self: &mut Self) -> Vec<QueryResult> {
+        self.iter().collect()
+    }
+
+    pub fn try_run(self: &mut Self) -> Result<Vec<QueryResult>, QueryError> {
+        self.try_iter().collect()
+    }
+
+    /// Lazily drives the pipes, computing one result per call to `next`.
+    /// Like `run`, this panics if the query fails.
+    pub fn iter(self: &mut Self) -> QueryIter<'_, 'a> {
+        QueryIter {
+            inner: self.try_iter()
+        }
+    }
+
+    pub fn try_iter(self: &mut Self) -> TryQueryIter<'_, 'a> {
+        TryQueryIter {
+            query: self,
+            failed: false
+        }
+    }
+    
+    /// Runs the query for up to `n` results, returning them along with a
+    /// cursor for the rest, or `None` if there is no more. Panics if the query
+    /// fails, see `try_run_page`.
+    pub fn run_page(self, n: usize) -> (Vec<QueryResult>, Option<Cursor<'a>>) {
+        Cursor::new(self).run_page(n)
+    }
+
+    pub fn try_run_page(self, n: usize) -> Result<(Vec<QueryResult>, Option<Cursor<'a>>), QueryError> {
+        Cursor::new(self).try_run_page(n)
+    }
+
+    // like try_run, but keeping the gremlins for sub-traversals
+    fn try_run_gremlins(self: &mut Self) -> Result<Vec<Gremlin>, QueryError> {
+        let mut gremlins = Vec::new();
+
+        while let Some(g) = self.next_gremlin()? {
+            gremlins.push(g);
+        }
+
+        Ok(gremlins)
+    }
+
+    // the next gremlin out of the program, carrying on from the last run
+    fn next_gremlin(self: &mut Self) -> Result<Option<Gremlin>, QueryError> {
+        self.transform();
+
+        let program = &mut self.program;
+        let machine = self.machine.get_or_insert_with(|| Machine::new(program.len()));
+
+        machine.next(self.graph, program)
+    }
+
+    fn transform(self: &mut Self) {
+
+        // TRANSFORM PROGRAM
+        if !self.transformed {
+            self.graph.transform(&mut self.program);
+            self.track_paths();
+            self.transformed = true;
+        }
+    }
+
+    // Paths cost a copy per step, so gremlins only keep them when asked to.
+    // The query a user runs decides, sub-traversals included; a seeded
+    // sub-query's gremlins keep a path exactly when its seed has one.
+    fn track_paths(self: &mut Self) {
+        if self.program.first().is_some_and(|p| p.is::<SeedPipe>()) || !uses_paths(self.graph, &self.program) {
+            return
+        }
+
+        for p in self.program.iter_mut() {
+            if let Some(v) = p.downcast_mut::<VertexPipe>() {
+                v.track = true;
+            }
+        }
+    }
+    
+    // // Pipetypes
+    pub fn vertex(self: &mut Self, filter: VertexFilter) -> &mut Self {
+        self.program.push(Box::new(VertexPipe::new(filter)));
+        self
+    }
+    
+    pub fn r#in(self: &mut Self, filter: EdgeFilter) -> &mut Self {
+        self.program.push(Box::new(SimpleTraversalPipe::new(SimpleTraversalDir::In, filter)));
+        self
+    }
+    
+    pub fn out(self: &mut Self, filter: EdgeFilter) -> &mut Self {
+        self.program.push(Box::new(SimpleTraversalPipe::new(SimpleTraversalDir::Out, filter)));
+        self
+    }
+    
+    pub fn both(self: &mut Self, filter: EdgeFilter) -> &mut Self {
+        self.program.push(Box::new(SimpleTraversalPipe::new(SimpleTraversalDir::Both, filter)));
+        self
+    }
+
+    pub fn in_e(self: &mut Self, filter: EdgeFilter) -> &mut Self {
+        self.program.push(Box::new(SimpleTraversalPipe::new_to_edges(SimpleTraversalDir::In, filter)));
+        self
+    }
+
+    pub fn out_e(self: &mut Self, filter: EdgeFilter) -> &mut Self {
+        self.program.push(Box::new(SimpleTraversalPipe::new_to_edges(SimpleTraversalDir::Out, filter)));
+        self
+    }
+
+    pub fn both_e(self: &mut Self, filter: EdgeFilter) -> &mut Self {
+        self.program.push(Box::new(SimpleTraversalPipe::new_to_edges(SimpleTraversalDir::Both, filter)));
+        self
+    }
+
+    pub fn in_v(self: &mut Self) -> &mut Self {
+        self.program.push(Box::new(EdgeVertexPipe::new(SimpleTraversalDir::In)));
+        self
+    }
+
+    pub fn out_v(self: &mut Self) -> &mut Self {
+        self.program.push(Box::new(EdgeVertexPipe::new(SimpleTraversalDir::Out)));
+        self
+    }
+
+    pub fn label(self: &mut Self) -> &mut Self {
+        self.program.push(Box::new(LabelPipe::new()));
+        self
+    }
+
+    pub fn property(self: &mut Self, property: String) -> &mut Self {
+        self.program.push(Box::new(PropertyPipe::new(property)));
+        self
+    }
+
+    /// Drops gremlins on vertices already seen, and any not on a vertex. See
+    /// `dedup` for values and edges.
+    pub fn unique(self: &mut Self) -> &mut Self {
+        self.program.push(Box::new(UniquePipe::new()));
+        self
+    }
+
+    /// Drops results equal to one already seen, whether values, vertices or
+    /// edges. Values containing NaN are never dropped.
+    pub fn dedup(self: &mut Self) -> &mut Self {
+        self.program.push(Box::new(DedupPipe::new(None)));
+        self
+    }
+
+    /// Drops gremlins whose key equals one already seen. Gremlins missing the
+    /// key count as Null.
+    pub fn dedup_by(self: &mut Self, key: Key) -> &mut Self {
+        self.program.push(Box::new(DedupPipe::new(Some(key))));
+        self
+    }
+
+    pub fn filter(self: &mut Self, filter:VertexFilter) -> &mut Self {
+        self.program.push(Box::new(FilterPipe::new(filter)));
+        self
+    }
+
+    pub fn filter_edges(self: &mut Self, filter:EdgeFilter) -> &mut Self {
+        self.program.push(Box::new(EdgeFilterPipe::new(filter)));
+        self
+    }
+
+    pub fn take(self: &mut Self, take:i64) -> &mut Self {
+        self.program.push(Box::new(TakePipe::new(take)));
+        self
+    }
+
+    /// Drops the first `skip` results; a negative skip drops none.
+    pub fn skip(self: &mut Self, skip:i64) -> &mut Self {
+        self.program.push(Box::new(SkipPipe::new(skip)));
+        self
+    }
+
+    /// The results from `start` up to but not including `end`. A negative
+    /// `start` counts as 0.
+    pub fn range(self: &mut Self, start:i64, end:i64) -> &mut Self {
+        let start = start.max(0);
+        self.skip(start).take(if end < start { 0 } else { end - start })
+    }
+
+    pub fn r#as(self: &mut Self, label:String) -> &mut Self {
+        self.program.push(Box::new(AsPipe::new(label)));
+        self
+    }
+
+    pub fn back(self: &mut Self, label:String) -> &mut Self {
+        self.program.push(Box::new(BackPipe::new(label)));
+        self
+    }
+
+    pub fn except(self: &mut Self, label:String) -> &mut Self {
+        self.program.push(Box::new(ExceptPipe::new(label)));
+        self
+    }
+
+    pub fn merge(self: &mut Self, labels:Vec<String>) -> &mut Self {
+        self.program.push(Box::new(MergePipe::new(labels)));
+        self
+    }
+
+    pub fn count(self: &mut Self) -> &mut Self {
+        self.program.push(Box::new(AggregatePipe::new(Aggregate::Count)));
+        self
+    }
+
+    /// Adds up the numeric values in the stream, skipping anything else. Ints
+    /// stay Int until a Float turns up or the sum overflows; an empty sum is 0.
+    pub fn sum(self: &mut Self) -> &mut Self {
+        self.program.push(Box::new(AggregatePipe::new(Aggregate::Sum)));
+        self
+    }
+
+    /// The smallest numeric value, Int and Float compared by value. Non-numeric
+    /// values and NaN are skipped; with nothing left there is no result.
+    pub fn min(self: &mut Self) -> &mut Self {
+        self.program.push(Box::new(AggregatePipe::new(Aggregate::Min)));
+        self
+    }
+
+    /// The largest numeric value, see `min`.
+    pub fn max(self: &mut Self) -> &mut Self {
+        self.program.push(Box::new(AggregatePipe::new(Aggregate::Max)));
+        self
+    }
+
+    /// The mean of the numeric values as a Float, see `sum`.
+    pub fn mean(self: &mut Self) -> &mut Self {
+        self.program.push(Box::new(AggregatePipe::new(Aggregate::Mean)));
+        self
+    }
+
+    /// Counts the gremlins sharing each value of `key`, giving an Object from
+    /// key to Int. Gremlins without a key are left out. String keys are used
+    /// as they are and others are written as JSON; two values that would
+    /// share a key, such as Int(1) and String("1"), fail with
+    /// `QueryError::GroupKeyClash`.
+    pub fn group_count(self: &mut Self, key: Key) -> &mut Self {
+        self.program.push(Box::new(GroupPipe::new(key, None)));
+        self
+    }
+
+    /// Groups gremlins by `key` into an Object of Arrays holding whatever
+    /// `values` yields when run from each member; vertices and edges in the
+    /// arrays become their ids. Pass `|q| q` to collect the members themselves.
+    /// Keys are made as in `group_count`.
+    pub fn group_by<F>(self: &mut Self, key: Key, values: F) -> &mut Self where F: for<'q, 'g> Fn(&'q mut Query<'g>) -> &'q mut Query<'g> + 'static {
+        self.program.push(Box::new(GroupPipe::new(key, Some(Box::new(values)))));
+        self
+    }
+
+    /// Sorts the stream by the first key, then the second, and so on. Gremlins
+    /// that tie on every key keep the order they arrived in.
+    pub fn order_by(self: &mut Self, keys: Vec<SortKey>) -> &mut Self {
+        self.program.push(Box::new(OrderPipe::new(keys)));
+        self
+    }
+
+    /// Runs `steps` again and again, each round starting from what the last
+    /// one reached, until nothing new turns up. A vertex or edge is visited at
+    /// most once per incoming gremlin, so cycles end. On its own it yields
+    /// where each walk ended, the gremlins `steps` leads nowhere from, so a
+    /// walk caught in a cycle yields nothing; `times`, `until` and `emit`
+    /// change that.
+    pub fn repeat<F>(self: &mut Self, steps: F) -> &mut Self where F: for<'q, 'g> Fn(&'q mut Query<'g>) -> &'q mut Query<'g> + 'static {
+        self.program.push(Box::new(RepeatPipe::new(Box::new(steps))));
+        self
+    }
+
+    /// Stops a `repeat` after `n` rounds, yielding what the last round reached.
+    pub fn times(self: &mut Self, n: usize) -> &mut Self {
+        self.modify_last("times", |r: &mut RepeatPipe| r.times = Some(n))
+    }
+
+    /// Stops a `repeat` at vertices matching `filter`, yielding them.
+    pub fn until(self: &mut Self, filter: VertexFilter) -> &mut Self {
+        self.modify_last("until", |r: &mut RepeatPipe| r.until = Some(filter))
+    }
+
+    /// Makes a `repeat` yield everything it reaches, not just where it stops.
+    pub fn emit(self: &mut Self) -> &mut Self {
+        self.modify_last("emit", |r: &mut RepeatPipe| r.emit = true)
+    }
+
+    // for steps like times that adjust the step before them
+    fn modify_last<P, F>(self: &mut Self, step: &str, f: F) -> &mut Self where P: Pipe, F: FnOnce(&mut P) {
+        match self.program.last_mut().and_then(|p| p.downcast_mut::<P>()) {
+            Some(r) => f(r),
+            None => self.program.push(Box::new(ErrorPipe::new(QueryError::MisplacedStep(step.to_string()))))
+        }
+        self
+    }
+
+    /// Replaces each result with an Array of the vertex and edge ids it was
+    /// reached through. Traversals like `out` only record the vertices; use
+    /// `out_e` and `in_v` to have the edges in the path too.
+    pub fn path(self: &mut Self) -> &mut Self {
+        self.program.push(Box::new(PathPipe));
+        self
+    }
+
+    /// Drops gremlins that have been on the same vertex twice.
+    pub fn simple_path(self: &mut Self) -> &mut Self {
+        self.program.push(Box::new(SimplePathPipe));
+        self
+    }
+
+    /// Turns each gremlin into an Object row holding what it labelled with
+    /// `as` under each of `labels`: the ids, or properties when followed by
+    /// `by`. A missing label fails the query.
+    pub fn select(self: &mut Self, labels: Vec<String>) -> &mut Self {
+        self.program.push(Box::new(SelectPipe::new(labels)));
+        self
+    }
+
+    /// Has a `select` give `property` rather than ids, Null where it is
+    /// missing. Several `by`s take turns over the labels.
+    pub fn by(self: &mut Self, property: String) -> &mut Self {
+        self.modify_last("by", |s: &mut SelectPipe| s.by.push(property))
+    }
+
+    /// Replaces each vertex or edge with an Object shaped like its JSON record:
+    /// its `id` and `properties`, plus `label`, `v_in` and `v_out` for edges.
+    /// `keys` picks the properties to include, `None` includes them all.
+    pub fn values_map(self: &mut Self, keys: Option<Vec<String>>) -> &mut Self {
+        self.program.push(Box::new(ValuesMapPipe::new(keys)));
+        self
+    }
+
+    /// Keeps the gremlins for which `steps` yields anything. Only the first
+    /// result of `steps` is ever computed.
+    pub fn where_<F>(self: &mut Self, steps: F) -> &mut Self where F: for<'q, 'g> Fn(&'q mut Query<'g>) -> &'q mut Query<'g> + 'static {
+        self.program.push(Box::new(WherePipe::new(Box::new(steps), false)));
+        self
+    }
+
+    /// Keeps the gremlins for which `steps` yields nothing.
+    pub fn not_<F>(self: &mut Self, steps: F) -> &mut Self where F: for<'q, 'g> Fn(&'q mut Query<'g>) -> &'q mut Query<'g> + 'static {
+        self.program.push(Box::new(WherePipe::new(Box::new(steps), true)));
+        self
+    }
+
+    /// Runs every branch from each gremlin, yielding all their results in
+    /// branch order.
+    pub fn union(self: &mut Self, branches: Vec<Traversal>) -> &mut Self {
+        self.program.push(Box::new(BranchPipe::new(Branch::Union(branches))));
+        self
+    }
+
+    /// Yields the results of the first branch that has any.
+    pub fn coalesce(self: &mut Self, branches: Vec<Traversal>) -> &mut Self {
+        self.program.push(Box::new(BranchPipe::new(Branch::Coalesce(branches))));
+        self
+    }
+
+    /// Yields the results of `steps`, or the gremlin itself if there are none.
+    pub fn optional<F>(self: &mut Self, steps: F) -> &mut Self where F: for<'q, 'g> Fn(&'q mut Query<'g>) -> &'q mut Query<'g> + 'static {
+        self.program.push(Box::new(BranchPipe::new(Branch::Optional(Box::new(steps)))));
+        self
+    }
+
+    /// Yields the results of `then` for gremlins where `test` yields anything,
+    /// as in `where_`, and those of `otherwise` for the rest.
+    pub fn choose<P, T, E>(self: &mut Self, test: P, then: T, otherwise: E) -> &mut Self
+    where P: for<'q, 'g> Fn(&'q mut Query<'g>) -> &'q mut Query<'g> + 'static,
+          T: for<'q, 'g> Fn(&'q mut Query<'g>) -> &'q mut Query<'g> + 'static,
+          E: for<'q, 'g> Fn(&'q mut Query<'g>) -> &'q mut Query<'g> + 'static {
+        self.program.push(Box::new(BranchPipe::new(Branch::Choose(Box::new(test), Box::new(then), Box::new(otherwise)))));
+        self
+    }
+
+    /// Appends a user-defined step.
+    pub fn pipe(self: &mut Self, pipe: Box<dyn Pipe>) -> &mut Self {
+        self.program.push(pipe);
+        self
+    }
+
+    /// Appends the steps registered under `name` with `Graph::add_alias`. An
+    /// unknown or self-referencing alias makes the query fail when run.
+    pub fn alias(self: &mut Self, name: &str) -> &mut Self {
+        let graph = self.graph;
+
+        let alias = match graph.aliases.get(name) {
+            Some(a) => a,
+            None => {
+                self.program.push(Box::new(ErrorPipe::new(QueryError::UnknownAlias(name.to_string()))));
+                return self
+            }
+        };
+
+        if self.expanding.iter().any(|n| n == name) {
+            self.program.push(Box::new(ErrorPipe::new(QueryError::RecursiveAlias(name.to_string()))));
+            return self
+        }
+
+        self.expanding.push(name.to_string());
+        (alias.f)(self);
+        self.expanding.pop();
+
+        self
+    }
+}
+
+
+pub struct TryQueryIter<'q, 'a> {
+    query: &'q mut Query<'a>,
+    failed: bool
+}
+
+
+impl<'q, 'a> Iterator for TryQueryIter<'q, 'a> {
+    type Item = Result<QueryResult, QueryError>;
+
+    fn next(self: &mut Self) -> Option<Result<QueryResult, QueryError>> {
+        if self.failed {
+            return None
+        }
+
+        match self.query.next_gremlin() {
+            Ok(Some(g)) => Some(Ok(g.to_query_result())),
+            Ok(None) => None,
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+
+/// Where a paged query left off. It owns the query, so the next page carries
+/// on without running anything again. One result past the page is worked out
+/// ahead of time to tell whether there is a next page at all.
+pub struct Cursor<'a> {
+    query: Query<'a>,
+    peeked: Option<Gremlin>
+}
+
+
+impl<'a> Cursor<'a> {
+    fn new(query: Query<'a>) -> Cursor<'a> {
+        Cursor {
+            query: query,
+            peeked: None
+        }
+    }
+
+    pub fn run_page(self, n: usize) -> (Vec<QueryResult>, Option<Cursor<'a>>) {
+        self.try_run_page(n).unwrap_or_else(|e| panic!("query failed: {}", e))
+    }
+
+    pub fn try_run_page(mut self, n: usize) -> Result<(Vec<QueryResult>, Option<Cursor<'a>>), QueryError> {
+        let mut page = Vec::new();
+
+        while page.len() < n {
+            match self.next_gremlin()? {
+                Some(g) => page.push(g.to_query_result()),
+                None => return Ok((page, None))
+            }
+        }
+
+        match self.next_gremlin()? {
+            Some(g) => {
+                self.peeked = Some(g);
+                Ok((page, Some(self)))
+            },
+            None => Ok((page, None))
+        }
+    }
+
+    fn next_gremlin(self: &mut Self) -> Result<Option<Gremlin>, QueryError> {
+        if let Some(g) = self.peeked.take() {
+            return Ok(Some(g))
+        }
+
+        self.query.next_gremlin()
+    }
+}
+
+
+pub struct QueryIter<'q, 'a> {
+    inner: TryQueryIter<'q, 'a>
+}
+
+
+impl<'q, 'a> Iterator for QueryIter<'q, 'a> {
+    type Item = QueryResult;
+
+    fn next(self: &mut Self) -> Option<QueryResult> {
+        self.inner.next().map(|r| r.unwrap_or_else(|e| panic!("query failed: {}", e)))
+    }
+}
+
+
+impl<'q, 'a> IntoIterator for &'q mut Query<'a> {
+    type Item = QueryResult;
+    type IntoIter = QueryIter<'q, 'a>;
+
+    fn into_iter(self) -> QueryIter<'q, 'a> {
+        self.iter()
+    }
+}
+
+
+pub type Program = Vec<Box<dyn Pipe>>;
+
+
+/// A step of a query. The machine calls `run` with a gremlin from the step
+/// before, or with `None` when it wants the step to produce one by itself;
+/// a step that has nothing to give answers `Pull` to be fed another gremlin,
+/// or `Done` once it never will again. `False` drops the gremlin it was given.
+pub trait Pipe: Any {
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin;
+
+    /// Called instead of pulling when every step before this one is done.
+    /// Steps that hold gremlins back, like aggregations, hand them out here;
+    /// anything but a gremlin marks the step as done.
+    fn finish(self: &mut Self, _graph: &Graph) -> MaybeGremlin {
+        MaybeGremlin::Done
+    }
+}
+
+
+impl dyn Pipe {
+    pub fn is<T: Pipe>(self: &Self) -> bool {
+        (self as &dyn Any).is::<T>()
+    }
+
+    pub fn downcast_ref<T: Pipe>(self: &Self) -> Option<&T> {
+        (self as &dyn Any).downcast_ref::<T>()
+    }
+
+    pub fn downcast_mut<T: Pipe>(self: &mut Self) -> Option<&mut T> {
+        (self as &mut dyn Any).downcast_mut::<T>()
+    }
+}
+
+
+pub struct VertexPipe {
+    init: bool,
+    vertices: Vec<u64>,
+    filter: VertexFilter,
+    track: bool,
+}
+
+
+impl VertexPipe {
+    fn new(filter: VertexFilter) -> VertexPipe {
+        VertexPipe {
+            init: false,
+            vertices: Vec::new(),
+            filter: filter,
+            track: false,
+        }
+    }
+}
+
+
+impl Pipe for VertexPipe {
+    
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        
+        if !self.init {
+            self.vertices.extend(graph.search_verticies(&self.filter));
+            if graph.ordered {
+                self.vertices.sort_by(|a, b| b.cmp(a));
+            }
+            self.init = true;
+        } 
+        
+        if self.vertices.is_empty() {
+            return MaybeGremlin::Done
+        }
+        
+        let vertex = self.vertices.pop().unwrap();
+
+        if !graph.vertices.contains_key(&vertex) {
+            return MaybeGremlin::Error(QueryError::VertexNotFound(vertex))
+        }
+            
+        let element = Element::Vertex(vertex);
+
+        return MaybeGremlin::Gremlin ( match gremlin {
+            Some(g) => g.moved(element),
+            None => Gremlin::starting_at(element, self.track)
+        } )
+    }
+}
+
+
+
+#[derive(Debug, Clone, Copy)]
+pub enum SimpleTraversalDir {
+    In,
+    Out,
+    Both
+}
+
+
+pub struct SimpleTraversalPipe {
+    dir: SimpleTraversalDir,
+    filter: EdgeFilter,
+    to_edges: bool,
+    edges: Vec<(u64, u64)>,
+    gremlin: Option<Gremlin>,
+}
+
+
+impl SimpleTraversalPipe {
+    fn new(dir: SimpleTraversalDir, filter: EdgeFilter) -> SimpleTraversalPipe {
+        SimpleTraversalPipe {
+            dir: dir,
+            filter: filter,
+            to_edges: false,
+            edges: Vec::new(),
+            gremlin: None,
+        }
+    }
+
+    // stops on the edges themselves rather than the vertices past them
+    fn new_to_edges(dir: SimpleTraversalDir, filter: EdgeFilter) -> SimpleTraversalPipe {
+        SimpleTraversalPipe {
+            to_edges: true,
+            ..SimpleTraversalPipe::new(dir, filter)
+        }
+    }
+
+    // (edge, vertex on the far side of the edge) pairs
+    fn get_edges(gremlin: &Option<Gremlin>, graph: &Graph, filter: &EdgeFilter, dir: SimpleTraversalDir) -> Result<Vec<(u64, u64)>, QueryError> {
+        let vertex_id = match gremlin.as_ref().and_then(|g| g.vertex) {
+            Some(v) => v,
+            None => return Ok(Vec::new())
+        };
+        let edges = match dir {
+            SimpleTraversalDir::Out => graph.get_out_edges(&vertex_id),
+            _ => graph.get_in_edges(&vertex_id),
+        }
+        .map_err(|_| QueryError::VertexNotFound(vertex_id))?;
+
+        Ok(edges.iter().filter(|edge| filter_edge(edge, filter))
+        .map(
+            |edge| {
+                match dir {
+                    SimpleTraversalDir::Out => (edge.id, edge.v_in), 
+                    _ => (edge.id, edge.v_out),
+                }
+            }
+        )
+        .collect())
+    }
+}
+
+
+impl Pipe for SimpleTraversalPipe {
+    
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if gremlin.is_none() && self.edges.is_empty() {
+            return MaybeGremlin::Pull
+        }
+        
+        if self.edges.is_empty() {
+            self.gremlin = gremlin;
+
+            match SimpleTraversalPipe::get_edges(&self.gremlin, graph, &self.filter, self.dir) {
+                Ok(bar) => self.edges.extend(bar),
+                Err(e) => return MaybeGremlin::Error(e)
+            }
+            
+            if let SimpleTraversalDir::Both = self.dir {
+                match SimpleTraversalPipe::get_edges(&self.gremlin, graph, &self.filter, SimpleTraversalDir::Out) {
+                    Ok(bar) => self.edges.extend(bar),
+                    Err(e) => return MaybeGremlin::Error(e)
+                }
+            }
+
+            if graph.ordered {
+                self.edges.sort_by_key(|e| std::cmp::Reverse(e.0));
+            }
+        }
+        
+        if self.edges.is_empty() {
+            return MaybeGremlin::Pull
+        }
+        
+        let (edge, vertex) = self.edges.pop().unwrap();
+
+        let element = if self.to_edges { Element::Edge(edge) } else { Element::Vertex(vertex) };
+        
+        return MaybeGremlin::Gremlin( self.gremlin.as_ref().unwrap().moved(element) )
+    }
+}
+
+
+pub struct EdgeVertexPipe {
+    dir: SimpleTraversalDir
+}
+
+
+impl EdgeVertexPipe {
+    fn new(dir: SimpleTraversalDir) -> EdgeVertexPipe {
+        EdgeVertexPipe {
+            dir: dir
+        }
+    }
+}
+
+
+impl Pipe for EdgeVertexPipe {
+    
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
+        } 
+
+        let g = gremlin.unwrap();
+
+        if let Option::None = g.edge {
+            return MaybeGremlin::Pull
+        }
+
+        let e_id = g.edge.unwrap();
+        let e = match graph.edges.get(&e_id) {
+            Some(e) => e,
+            None => return MaybeGremlin::Error(QueryError::EdgeNotFound(e_id))
+        };
+
+        let v = match self.dir {
+            SimpleTraversalDir::In => e.v_in,
+            _ => e.v_out
+        };
+
+        return MaybeGremlin::Gremlin( g.moved(Element::Vertex(v)) )
+    }
+}
+
+
+pub struct LabelPipe {
+}
+
+
+impl LabelPipe {
+    fn new() -> LabelPipe {
+        LabelPipe {
+        }
+    }
+}
+
+
+impl Pipe for LabelPipe {
+    
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
+        } 
+
+        let mut g = gremlin.unwrap();
+
+        if let Option::None = g.edge {
+            return MaybeGremlin::False
+        }
+
+        let e_id = g.edge.unwrap();
+        match graph.edges.get(&e_id) {
+            Some(e) => g.result = Some(Value::String(e.label.clone())),
+            None => return MaybeGremlin::Error(QueryError::EdgeNotFound(e_id))
+        }
+
+        return MaybeGremlin::Gremlin ( g )
+    }
+}
+
+
+pub struct PropertyPipe {
+    property: String
+}
+
+
+impl PropertyPipe {
+    fn new(property: String) -> PropertyPipe {
+        PropertyPipe {
+            property: property
+        }
+    }
+}
+
+
+impl Pipe for PropertyPipe {
+    
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+       
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
+        } 
+
+        let g = gremlin.unwrap();
+
+        let r = match element_property(graph, &g, &self.property) {
+            Ok(r) => r,
+            Err(e) => return MaybeGremlin::Error(e)
+        };
+        
+        if let Option::None = r {
+            return MaybeGremlin::False
+        } else {
+            let mut g2 = g.clone();
+            g2.result = Some(r.unwrap().clone());
+            return MaybeGremlin::Gremlin ( g2 )
+        }
+    }
+}
+
+
+pub struct UniquePipe {
+    seen: HashSet<u64>
+}
+
+
+impl UniquePipe {
+    fn new() -> UniquePipe {
+        UniquePipe {
+            seen: HashSet::new()
+        }
+    }
+}
+
+
+impl Pipe for UniquePipe {
+    
+    fn run(self: &mut Self, _graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
+        } 
+
+        let g = gremlin.unwrap();
+
+        if let Option::None = g.vertex {
+            return MaybeGremlin::Pull
+        }
+
+        let v = g.vertex.unwrap();
+
+        if !self.seen.insert(v) {
+            return MaybeGremlin::Pull
+        } 
+        
+        return MaybeGremlin::Gremlin ( g.clone() )
+    }
+}
+
+
+#[derive(PartialEq, Eq, Hash)]
+enum DedupKey {
+    Element(Element),
+    Value(IndexKey)
+}
+
+
+pub struct DedupPipe {
+    key: Option<Key>,
+    seen: HashSet<DedupKey>
+}
+
+
+impl DedupPipe {
+    fn new(key: Option<Key>) -> DedupPipe {
+        DedupPipe {
+            key: key,
+            seen: HashSet::new()
+        }
+    }
+
+    // None for values that equal nothing, not even themselves
+    fn key(self: &Self, graph: &Graph, g: &Gremlin) -> Result<Option<DedupKey>, QueryError> {
+        let value = match &self.key {
+            Some(key) => key_value(graph, g, key)?.unwrap_or(Value::Null),
+            None => match (&g.result, g.element()) {
+                (Some(result), _) => result.clone(),
+                (None, Some(e)) => return Ok(Some(DedupKey::Element(e))),
+                (None, None) => Value::Null
+            }
+        };
+        Ok(IndexKey::new(&value).map(DedupKey::Value))
+    }
+}
+
+
+impl Pipe for DedupPipe {
+
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
+        }
+
+        let g = gremlin.unwrap();
+
+        match self.key(graph, &g) {
+            Ok(Some(k)) => if !self.seen.insert(k) {
+                return MaybeGremlin::Pull
+            },
+            Ok(None) => {},
+            Err(e) => return MaybeGremlin::Error(e)
+        }
+
+        return MaybeGremlin::Gremlin(g)
+    }
+}
+
+
+// // TODO implement something like this
+// pub enum FilterPipeArg {
+//     VertexFilter(VertexFilter),
+//     Closure(Box<dyn Fn(&Vertex) -> bool>)
+// }
+
+
+pub struct FilterPipe {
+    init: bool,
+    filter: VertexFilter,
+    indexed: Option<Vec<(String, Option<IndexKey>)>>
+}
+
+
+impl FilterPipe {
+    fn new(filter: VertexFilter) -> FilterPipe {
+        FilterPipe {
+            init: false,
+            filter: filter,
+            indexed: None
+        }
+    }
+}
+
+
+impl Pipe for FilterPipe {
+    
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
+        } 
+
+        let g = gremlin.unwrap();
+
+        if let Option::None = g.vertex {
+            return MaybeGremlin::Pull
+        }
+
+        if !self.init {
+            if let VertexFilter::Props(f) = &self.filter {
+                self.indexed = graph.index_keys(f);
+            }
+            self.init = true;
+        }
+
+        let v_id = g.vertex.unwrap();
+
+        let matched = match &self.indexed {
+            Some(keys) => keys.iter().all(|(k, key)| graph.index_contains(k, key, v_id)),
+            None => match graph.get_vertex(&v_id) {
+                Some(v) => filter_vertex(v, &self.filter),
+                None => return MaybeGremlin::Error(QueryError::VertexNotFound(v_id))
+            }
+        };
+
+        if matched {
+            return MaybeGremlin::Gremlin ( g.clone() ) 
+        } else {
+            return MaybeGremlin::Pull
+        }
+    }
+}
+
+
+pub struct EdgeFilterPipe {
+    filter: EdgeFilter
+}
+
+
+impl EdgeFilterPipe {
+    fn new(filter: EdgeFilter) -> EdgeFilterPipe {
+        EdgeFilterPipe {
+            filter: filter
+        }
+    }
+}
+
+
+impl Pipe for EdgeFilterPipe {
+    
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
+        } 
+
+        let g = gremlin.unwrap();
+
+        if let Option::None = g.edge {
+            return MaybeGremlin::Pull
+        }
+
+        let e_id = g.edge.unwrap();
+        let e = match graph.edges.get(&e_id) {
+            Some(e) => e,
+            None => return MaybeGremlin::Error(QueryError::EdgeNotFound(e_id))
+        };
+
+        if filter_edge(e, &self.filter) {
+            return MaybeGremlin::Gremlin ( g ) 
+        } else {
+            return MaybeGremlin::Pull
+        }
+    }
+}
+
+
+pub struct TakePipe {
+    taken: i64,
+    take: i64,
+}
+
+
+impl TakePipe {
+    fn new(take: i64) -> TakePipe {
+        TakePipe {
+            taken: 0,
+            take: take
+        }
+    }
+}
+
+
+impl Pipe for TakePipe {
+    
+    fn run(self: &mut Self, _graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if self.taken == self.take {
+            self.taken = 0;
+            return  MaybeGremlin::Done
+        }
+
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
+        } 
+
+        let g = gremlin.unwrap();
+
+        self.taken += 1;
+
+        return MaybeGremlin::Gremlin ( g.clone() )  
+    }
+}
+
+
+pub struct SkipPipe {
+    skipped: i64,
+    skip: i64,
+}
+
+
+impl SkipPipe {
+    fn new(skip: i64) -> SkipPipe {
+        SkipPipe {
+            skipped: 0,
+            skip: skip.max(0)
+        }
+    }
+}
+
+
+impl Pipe for SkipPipe {
+
+    fn run(self: &mut Self, _graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
+        }
+
+        if self.skipped < self.skip {
+            self.skipped += 1;
+            return MaybeGremlin::Pull
+        }
+
+        return MaybeGremlin::Gremlin ( gremlin.unwrap() )
+    }
+}
+
+
+pub struct AsPipe {
+    label: String
+}
+
+
+impl AsPipe {
+    fn new(label: String) -> AsPipe {
+        AsPipe {
+            label: label
+        }
+    }
+}
+
+
+impl Pipe for AsPipe {
+    
+    fn run(self: &mut Self, _graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
+        } 
+
+        let mut g = gremlin.unwrap();
+
+        if let Option::None = g.element() {
+            return MaybeGremlin::Pull
+        }
+
+        let v = g.element().unwrap();
+
+        if g.r#as.is_none() { 
+            g.r#as = Some(HashMap::new()); 
+        }
+
+        let p = g.r#as.as_mut().unwrap();
         
-        let vertex = self.vertices.pop().unwrap();
-            
-        return MaybeGremlin::Gremlin ( Gremlin {
-            vertex: Some(vertex),
-            r#as: match gremlin { Some(g) => g.r#as.clone(), None => None },
-            result: None
-        })
+        p.insert(self.label.clone(), v);
+
+        let g = g.clone();
+
+        return MaybeGremlin::Gremlin ( g )
+    }
+}
+
+
+pub struct BackPipe {
+    label: String
+}
+
+
+impl BackPipe {
+    fn new(label: String) -> BackPipe {
+        BackPipe {
+            label: label
+        }
+    }
+}
+
+
+impl Pipe for BackPipe {
+    
+    fn run(self: &mut Self, _graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
+        } 
+
+        let g = gremlin.unwrap();
+
+        let v = match g.r#as.as_ref().and_then(|a| a.get(&self.label)) {
+            Some(v) => *v,
+            None => return MaybeGremlin::Error(QueryError::MissingLabel(self.label.clone()))
+        };
+
+        let mut h = g.moved(v);
+        h.r#as = None;
+
+        return MaybeGremlin::Gremlin( h )
+    }
+}
+
+
+pub struct ExceptPipe {
+    label: String
+}
+
+
+impl ExceptPipe {
+    fn new(label: String) -> ExceptPipe {
+        ExceptPipe {
+            label: label
+        }
+    }
+}
+
+
+impl Pipe for ExceptPipe {
+    
+    fn run(self: &mut Self, _graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
+        } 
+
+        let g = gremlin.unwrap();
+
+        let a_v = match g.r#as.as_ref().and_then(|a| a.get(&self.label)) {
+            Some(v) => *v,
+            None => return MaybeGremlin::Error(QueryError::MissingLabel(self.label.clone()))
+        };
+
+        if let Option::None = g.element() {
+            return MaybeGremlin::Pull
+        }
+
+        let v = g.element().unwrap();
+
+        if v == a_v {
+            return MaybeGremlin::Pull
+        }
+
+        return MaybeGremlin::Gremlin ( g.clone() )
+    }
+}
+
+
+pub struct MergePipe {
+    labels:Vec<String>,
+    vertices:Option<Vec<Element>>
+}
+
+
+impl MergePipe {
+    fn new(labels:Vec<String>) -> MergePipe {
+        MergePipe {
+            labels: labels,
+            vertices: None
+        }
+    }
+}
+
+
+impl Pipe for MergePipe {
+    
+    fn run(self: &mut Self, _graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if self.vertices.is_none() && gremlin.is_none() {
+            return MaybeGremlin::Pull
+        }
+
+        if self.vertices.is_none() || self.vertices.as_ref().unwrap().is_empty() {
+
+            if let Some(g) = &gremlin {
+                let a = match g.r#as.as_ref() { None => HashMap::new(), Some(s) => s.clone() };
+                self.vertices = Some(self.labels.iter().filter_map( |id| a.get(id).copied() ).collect());
+            } else {
+                self.vertices = Some(Vec::new());
+            }
+        }
+
+        if self.vertices.as_ref().unwrap().is_empty() {
+            return MaybeGremlin::Pull
+        }
+
+        let v = self.vertices.as_mut().unwrap().pop().unwrap();
+
+        return MaybeGremlin::Gremlin( match &gremlin { None => Gremlin::at(v, None), Some(g) => g.moved(v) } )
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Aggregate {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Mean
+}
+
+
+pub struct AggregatePipe {
+    op: Aggregate,
+    count: i64,
+    acc: Option<Value>,
+    finished: bool
+}
+
+
+impl AggregatePipe {
+    fn new(op: Aggregate) -> AggregatePipe {
+        AggregatePipe {
+            op: op,
+            count: 0,
+            acc: None,
+            finished: false
+        }
+    }
+}
+
+
+impl Pipe for AggregatePipe {
+
+    fn run(self: &mut Self, _graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
+        }
+
+        let g = gremlin.unwrap();
+
+        if let Aggregate::Count = self.op {
+            self.count += 1;
+            return MaybeGremlin::Pull
+        }
+
+        let v = match g.result {
+            Some(Value::Float(f)) if f.is_nan() && (self.op == Aggregate::Min || self.op == Aggregate::Max) => return MaybeGremlin::Pull,
+            Some(v @ Value::Int(_)) | Some(v @ Value::Float(_)) => v,
+            _ => return MaybeGremlin::Pull
+        };
+
+        self.count += 1;
+
+        self.acc = match (self.op, self.acc.take()) {
+            (_, None) => Some(v),
+            (Aggregate::Min, Some(a)) => Some(if compare_values(&v, &a) == Some(Ordering::Less) { v } else { a }),
+            (Aggregate::Max, Some(a)) => Some(if compare_values(&v, &a) == Some(Ordering::Greater) { v } else { a }),
+            (_, Some(a)) => Some(add_numbers(&a, &v))
+        };
+
+        return MaybeGremlin::Pull
     }
-}
 
+    fn finish(self: &mut Self, _graph: &Graph) -> MaybeGremlin {
+        if self.finished {
+            return MaybeGremlin::Done
+        }
 
+        self.finished = true;
 
-#[derive(Debug, Clone, Copy)]
-pub enum SimpleTraversalDir {
-    In,
-    Out,
-    Both
+        let result = match self.op {
+            Aggregate::Count => Some(Value::Int(self.count)),
+            Aggregate::Sum => Some(self.acc.take().unwrap_or(Value::Int(0))),
+            Aggregate::Min | Aggregate::Max => self.acc.take(),
+            Aggregate::Mean => self.acc.take().map(|a| Value::Float(as_float(&a) / self.count as f64))
+        };
+
+        match result {
+            Some(v) => MaybeGremlin::Gremlin(Gremlin::new_value(v)),
+            None => MaybeGremlin::Done
+        }
+    }
 }
 
 
-pub struct SimpleTraversalPipe<'a> {
-    graph: &'a Graph,
-    dir: SimpleTraversalDir,
-    filter: EdgeFilter,
-    edges: Vec<u64>,
-    gremlin: Option<Gremlin>,
+#[derive(Debug, Clone)]
+pub enum Key {
+    /// the vertex or edge id
+    Id,
+    Property(String),
+    /// the value of a preceding `property` or `label` step
+    Value
 }
 
 
-impl<'a> SimpleTraversalPipe<'a> {
-    fn new(graph: &'a Graph, dir: SimpleTraversalDir, filter: EdgeFilter) -> SimpleTraversalPipe<'a> {
-        SimpleTraversalPipe {
-            graph: graph,
-            dir: dir,
-            filter: filter,
-            edges: Vec::new(),
-            gremlin: None,
+pub struct GroupPipe {
+    key: Key,
+    values: Option<Traversal>,
+    groups: HashMap<String, Value>,
+    // the value each group key was made from
+    sources: HashMap<String, Option<IndexKey>>,
+    finished: bool
+}
+
+
+impl GroupPipe {
+    fn new(key: Key, values: Option<Traversal>) -> GroupPipe {
+        GroupPipe {
+            key: key,
+            values: values,
+            groups: HashMap::new(),
+            sources: HashMap::new(),
+            finished: false
         }
     }
 
-    fn get_edges(gremlin: &Option<Gremlin>, graph: &Graph, filter: &EdgeFilter, dir: SimpleTraversalDir) -> Vec<u64> {
-        let vertex_id = gremlin.as_ref().unwrap().vertex.unwrap();
-        match dir {
-            SimpleTraversalDir::Out => graph.get_out_edges(&vertex_id),
-            _ => graph.get_in_edges(&vertex_id),
+    // Strings are their own key and other values are written as canonical
+    // JSON, so Int(1) and String("1") would share a key. That is an error
+    // rather than silently merging the groups.
+    fn key(self: &mut Self, graph: &Graph, g: &Gremlin) -> Result<Option<String>, QueryError> {
+        let value = match key_value(graph, g, &self.key)? {
+            Some(v) => v,
+            None => return Ok(None)
+        };
+
+        let key = match &value {
+            Value::String(s) => s.clone(),
+            v => canonical_json(v).to_string()
+        };
+
+        let source = IndexKey::new(&value);
+        match self.sources.get(&key) {
+            Some(s) if *s != source => return Err(QueryError::GroupKeyClash(key)),
+            Some(_) => {},
+            None => {
+                self.sources.insert(key.clone(), source);
+            }
         }
-        .iter().filter(|edge| filter_edge(edge, filter))
-        .map(
-            |edge| {
-                match dir {
-                    SimpleTraversalDir::Out => edge.v_in, 
-                    _ => edge.v_out,
+
+        Ok(Some(key))
+    }
+}
+
+
+impl Pipe for GroupPipe {
+
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
+        }
+
+        let g = gremlin.unwrap();
+
+        let key = match self.key(graph, &g) {
+            Ok(Some(k)) => k,
+            Ok(None) => return MaybeGremlin::Pull,
+            Err(e) => return MaybeGremlin::Error(e)
+        };
+
+        let values = match &self.values {
+            None => {
+                let n = self.groups.entry(key).or_insert(Value::Int(0));
+                if let Value::Int(n) = n {
+                    *n += 1;
+                }
+                return MaybeGremlin::Pull
+            },
+            Some(f) => {
+                let mut sub = Query::seeded(graph, g);
+                f(&mut sub);
+                match sub.try_run() {
+                    Ok(results) => results,
+                    Err(e) => return MaybeGremlin::Error(e)
                 }
             }
-        )
-        .collect()
+        };
+
+        if let Value::Array(a) = self.groups.entry(key).or_insert(Value::Array(Vec::new())) {
+            a.extend(values.into_iter().map(QueryResult::into_value));
+        }
+
+        return MaybeGremlin::Pull
+    }
+
+    fn finish(self: &mut Self, _graph: &Graph) -> MaybeGremlin {
+        if self.finished {
+            return MaybeGremlin::Done
+        }
+
+        self.finished = true;
+
+        return MaybeGremlin::Gremlin(Gremlin::new_value(Value::Object(std::mem::take(&mut self.groups))))
     }
 }
 
 
-impl<'a> Pipe for SimpleTraversalPipe<'a> {
-    
-    fn run(self: &mut Self, gremlin: Option<Gremlin>) -> MaybeGremlin {
-        if gremlin.is_none() && self.edges.is_empty() {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    Asc,
+    Desc
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Nulls {
+    First,
+    Last
+}
+
+
+/// One key of an `order_by`. Missing keys and `Value::Null` count as nulls,
+/// which go last unless asked otherwise, whatever the order.
+#[derive(Debug, Clone)]
+pub struct SortKey {
+    pub key: Key,
+    pub order: Order,
+    pub nulls: Nulls
+}
+
+
+impl SortKey {
+    pub fn asc(key: Key) -> SortKey {
+        SortKey {
+            key: key,
+            order: Order::Asc,
+            nulls: Nulls::Last
+        }
+    }
+
+    pub fn desc(key: Key) -> SortKey {
+        SortKey {
+            order: Order::Desc,
+            ..SortKey::asc(key)
+        }
+    }
+
+    pub fn nulls_first(self) -> SortKey {
+        SortKey {
+            nulls: Nulls::First,
+            ..self
+        }
+    }
+
+    pub fn nulls_last(self) -> SortKey {
+        SortKey {
+            nulls: Nulls::Last,
+            ..self
+        }
+    }
+
+    fn compare(self: &Self, a: &Option<Value>, b: &Option<Value>) -> Ordering {
+        let null = |v: &Option<Value>| matches!(v, None | Some(Value::Null));
+
+        match (null(a), null(b)) {
+            (true, true) => Ordering::Equal,
+            (true, false) => if self.nulls == Nulls::First { Ordering::Less } else { Ordering::Greater },
+            (false, true) => if self.nulls == Nulls::First { Ordering::Greater } else { Ordering::Less },
+            (false, false) => {
+                let o = a.as_ref().unwrap().total_cmp(b.as_ref().unwrap());
+                if self.order == Order::Desc { o.reverse() } else { o }
+            }
+        }
+    }
+}
+
+
+pub struct OrderPipe {
+    keys: Vec<SortKey>,
+    gremlins: Vec<(Vec<Option<Value>>, Gremlin)>,
+    sorted: bool
+}
+
+
+impl OrderPipe {
+    fn new(keys: Vec<SortKey>) -> OrderPipe {
+        OrderPipe {
+            keys: keys,
+            gremlins: Vec::new(),
+            sorted: false
+        }
+    }
+}
+
+
+impl Pipe for OrderPipe {
+
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
             return MaybeGremlin::Pull
         }
-        
-        if self.edges.is_empty() {
-            self.gremlin = gremlin;
 
-            let bar = SimpleTraversalPipe::get_edges(&self.gremlin, self.graph, &self.filter, self.dir); 
-            self.edges.extend(bar);
-            
-            if let SimpleTraversalDir::Both = self.dir {
-                let bar = SimpleTraversalPipe::get_edges(&self.gremlin, self.graph, &self.filter, SimpleTraversalDir::Out);
-                self.edges.extend(bar);
+        let g = gremlin.unwrap();
+
+        let mut values = Vec::new();
+
+        for k in &self.keys {
+            match key_value(graph, &g, &k.key) {
+                Ok(v) => values.push(v),
+                Err(e) => return MaybeGremlin::Error(e)
             }
         }
-        
-        if self.edges.is_empty() {
+
+        self.gremlins.push((values, g));
+
+        return MaybeGremlin::Pull
+    }
+
+    fn finish(self: &mut Self, _graph: &Graph) -> MaybeGremlin {
+        if !self.sorted {
+            let keys = &self.keys;
+
+            // stable, so ties keep their order; reversed to hand out with pop
+            self.gremlins.sort_by(|(a, _), (b, _)| {
+                keys.iter().zip(a.iter().zip(b.iter()))
+                    .map(|(k, (a, b))| k.compare(a, b))
+                    .find(|o| *o != Ordering::Equal)
+                    .unwrap_or(Ordering::Equal)
+            });
+            self.gremlins.reverse();
+            self.sorted = true;
+        }
+
+        match self.gremlins.pop() {
+            Some((_, g)) => MaybeGremlin::Gremlin(g),
+            None => MaybeGremlin::Done
+        }
+    }
+}
+
+
+pub struct RepeatPipe {
+    steps: Traversal,
+    times: Option<usize>,
+    until: Option<VertexFilter>,
+    emit: bool,
+    results: Vec<Gremlin>
+}
+
+
+impl RepeatPipe {
+    fn new(steps: Traversal) -> RepeatPipe {
+        RepeatPipe {
+            steps: steps,
+            times: None,
+            until: None,
+            emit: false,
+            results: Vec::new()
+        }
+    }
+
+    fn stops_at(self: &Self, graph: &Graph, g: &Gremlin) -> Result<bool, QueryError> {
+        match (&self.until, g.vertex) {
+            (Some(filter), Some(id)) => match graph.get_vertex(&id) {
+                Some(v) => Ok(filter_vertex(v, filter)),
+                None => Err(QueryError::VertexNotFound(id))
+            },
+            _ => Ok(false)
+        }
+    }
+
+    // breadth first, so everything is reached at its shallowest depth
+    fn expand(self: &Self, graph: &Graph, start: Gremlin) -> Result<Vec<Gremlin>, QueryError> {
+        if self.times == Some(0) {
+            return Ok(vec![start])
+        }
+
+        let open_ended = self.times.is_none() && self.until.is_none() && !self.emit;
+
+        let mut visited = HashSet::new();
+        let mut out = Vec::new();
+
+        if let Some(e) = start.element() {
+            visited.insert(e);
+        }
+
+        let mut frontier = vec![start];
+        let mut depth = 0;
+
+        while !frontier.is_empty() {
+            depth += 1;
+
+            let mut next = Vec::new();
+
+            for g in frontier {
+                let found = run_traversal(graph, &g, &self.steps)?;
+
+                // only a gremlin with nowhere left to go ends its walk, not
+                // one whose next steps were all reached some other way
+                if open_ended && found.is_empty() && depth > 1 {
+                    out.push(g);
+                }
+
+                for h in found {
+                    // values can't be walked any further
+                    match h.element() {
+                        Some(e) => if !visited.insert(e) { continue },
+                        None => {
+                            out.push(h);
+                            continue
+                        }
+                    }
+
+                    if self.times == Some(depth) || self.stops_at(graph, &h)? {
+                        out.push(h);
+                    } else {
+                        if self.emit {
+                            out.push(h.clone());
+                        }
+                        next.push(h);
+                    }
+                }
+            }
+
+            frontier = next;
+        }
+
+        Ok(out)
+    }
+}
+
+
+impl Pipe for RepeatPipe {
+
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if gremlin.is_none() && self.results.is_empty() {
             return MaybeGremlin::Pull
         }
-        
-        let vertex = self.edges.pop();
-        
-        return MaybeGremlin::Gremlin( Gremlin {
-            vertex: vertex,
-            r#as: self.gremlin.as_ref().unwrap().r#as.clone(),
-            result: None
-        })
+
+        if self.results.is_empty() {
+            match self.expand(graph, gremlin.unwrap()) {
+                Ok(mut results) => {
+                    results.reverse();
+                    self.results = results;
+                },
+                Err(e) => return MaybeGremlin::Error(e)
+            }
+        }
+
+        match self.results.pop() {
+            Some(g) => MaybeGremlin::Gremlin(g),
+            None => MaybeGremlin::Pull
+        }
     }
 }
 
 
-pub struct PropertyPipe<'a> {
-    graph: &'a Graph,
-    property: String
+pub struct SelectPipe {
+    labels: Vec<String>,
+    by: Vec<String>
 }
 
 
-impl<'a> PropertyPipe<'a> {
-    fn new(graph: &'a Graph, property: String) -> PropertyPipe {
-        PropertyPipe {
-            graph: graph,
-            property: property
+impl SelectPipe {
+    fn new(labels: Vec<String>) -> SelectPipe {
+        SelectPipe {
+            labels: labels,
+            by: Vec::new()
+        }
+    }
+
+    fn row(self: &Self, graph: &Graph, g: &Gremlin) -> Result<HashMap<String, Value>, QueryError> {
+        let mut row = HashMap::new();
+
+        for (i, label) in self.labels.iter().enumerate() {
+            let element = match g.r#as.as_ref().and_then(|a| a.get(label)) {
+                Some(e) => *e,
+                None => return Err(QueryError::MissingLabel(label.clone()))
+            };
+
+            let value = if self.by.is_empty() {
+                match element { Element::Vertex(id) | Element::Edge(id) => Value::Int(id as i64) }
+            } else {
+                element_properties(graph, element)?.get(&self.by[i % self.by.len()]).cloned().unwrap_or(Value::Null)
+            };
+
+            row.insert(label.clone(), value);
         }
+
+        Ok(row)
     }
 }
 
 
-impl<'a> Pipe for PropertyPipe<'a> {
-    
-    fn run(self: &mut Self, gremlin: Option<Gremlin>) -> MaybeGremlin {
-       
+impl Pipe for SelectPipe {
+
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
         if let Option::None = gremlin {
             return MaybeGremlin::Pull
-        } 
+        }
 
-        let g = gremlin.unwrap();
+        let mut g = gremlin.unwrap();
 
-        if let Option::None = g.vertex {
-            return MaybeGremlin::False
+        match self.row(graph, &g) {
+            Ok(row) => g.result = Some(Value::Object(row)),
+            Err(e) => return MaybeGremlin::Error(e)
         }
 
-        let v_id = g.vertex.unwrap();
-        let v = self.graph.get_vertex(&v_id).unwrap();
+        return MaybeGremlin::Gremlin ( g )
+    }
+}
 
-        let r = v.properties.get(&self.property);
-        
-        if let Option::None = r {
-            return MaybeGremlin::False
-        } else {
-            let mut g2 = g.clone();
-            g2.result = Some(r.unwrap().clone());
-            return MaybeGremlin::Gremlin ( g2 )
+
+pub struct ValuesMapPipe {
+    keys: Option<Vec<String>>
+}
+
+
+impl ValuesMapPipe {
+    fn new(keys: Option<Vec<String>>) -> ValuesMapPipe {
+        ValuesMapPipe {
+            keys: keys
         }
     }
+
+    fn pick(self: &Self, properties: &HashMap<String, Value>) -> Value {
+        Value::Object(match &self.keys {
+            None => properties.clone(),
+            Some(keys) => keys.iter().filter_map(|k| properties.get(k).map(|v| (k.clone(), v.clone()))).collect()
+        })
+    }
 }
 
 
-pub struct UniquePipe {
-    seen: HashSet<u64>
+impl Pipe for ValuesMapPipe {
+
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
+        }
+
+        let mut g = gremlin.unwrap();
+
+        let mut map = HashMap::new();
+
+        match g.element() {
+            None => return MaybeGremlin::False,
+            Some(Element::Vertex(id)) => match graph.get_vertex(&id) {
+                Some(v) => {
+                    map.insert("id".to_string(), Value::Int(v.id as i64));
+                    map.insert("properties".to_string(), self.pick(&v.properties));
+                },
+                None => return MaybeGremlin::Error(QueryError::VertexNotFound(id))
+            },
+            Some(Element::Edge(id)) => match graph.edges.get(&id) {
+                Some(e) => {
+                    map.insert("id".to_string(), Value::Int(e.id as i64));
+                    map.insert("label".to_string(), Value::String(e.label.clone()));
+                    map.insert("v_in".to_string(), Value::Int(e.v_in as i64));
+                    map.insert("v_out".to_string(), Value::Int(e.v_out as i64));
+                    map.insert("properties".to_string(), self.pick(&e.properties));
+                },
+                None => return MaybeGremlin::Error(QueryError::EdgeNotFound(id))
+            }
+        }
+
+        g.result = Some(Value::Object(map));
+
+        return MaybeGremlin::Gremlin ( g )
+    }
 }
 
 
-impl UniquePipe {
-    fn new() -> UniquePipe {
-        UniquePipe {
-            seen: HashSet::new()
+pub struct WherePipe {
+    steps: Traversal,
+    negate: bool
+}
+
+
+impl WherePipe {
+    fn new(steps: Traversal, negate: bool) -> WherePipe {
+        WherePipe {
+            steps: steps,
+            negate: negate
         }
     }
 }
 
 
-impl Pipe for UniquePipe {
-    
-    fn run(self: &mut Self, gremlin: Option<Gremlin>) -> MaybeGremlin {
+impl Pipe for WherePipe {
+
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
         if let Option::None = gremlin {
             return MaybeGremlin::Pull
-        } 
+        }
 
         let g = gremlin.unwrap();
 
-        if let Option::None = g.vertex {
+        let found = match traversal_yields(graph, &g, &self.steps) {
+            Ok(found) => found,
+            Err(e) => return MaybeGremlin::Error(e)
+        };
+
+        if found == self.negate {
             return MaybeGremlin::Pull
         }
 
-        let v = g.vertex.unwrap();
+        return MaybeGremlin::Gremlin ( g )
+    }
+}
 
-        if !self.seen.insert(v) {
+
+enum Branch {
+    Union(Vec<Traversal>),
+    Coalesce(Vec<Traversal>),
+    Optional(Traversal),
+    Choose(Traversal, Traversal, Traversal)
+}
+
+
+pub struct BranchPipe {
+    branch: Branch,
+    results: Vec<Gremlin>
+}
+
+
+impl BranchPipe {
+    fn new(branch: Branch) -> BranchPipe {
+        BranchPipe {
+            branch: branch,
+            results: Vec::new()
+        }
+    }
+
+    fn branch(self: &Self, graph: &Graph, g: Gremlin) -> Result<Vec<Gremlin>, QueryError> {
+        match &self.branch {
+            Branch::Union(branches) => {
+                let mut out = Vec::new();
+                for b in branches {
+                    out.extend(run_traversal(graph, &g, b)?);
+                }
+                Ok(out)
+            },
+            Branch::Coalesce(branches) => {
+                for b in branches {
+                    let out = run_traversal(graph, &g, b)?;
+                    if !out.is_empty() {
+                        return Ok(out)
+                    }
+                }
+                Ok(Vec::new())
+            },
+            Branch::Optional(steps) => {
+                let out = run_traversal(graph, &g, steps)?;
+                Ok(if out.is_empty() { vec![g] } else { out })
+            },
+            Branch::Choose(test, then, otherwise) => {
+                if traversal_yields(graph, &g, test)? {
+                    run_traversal(graph, &g, then)
+                } else {
+                    run_traversal(graph, &g, otherwise)
+                }
+            }
+        }
+    }
+}
+
+
+impl Pipe for BranchPipe {
+
+    fn run(self: &mut Self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if gremlin.is_none() && self.results.is_empty() {
             return MaybeGremlin::Pull
-        } 
-        
-        return MaybeGremlin::Gremlin ( g.clone() )
+        }
+
+        if self.results.is_empty() {
+            match self.branch(graph, gremlin.unwrap()) {
+                Ok(mut results) => {
+                    results.reverse();
+                    self.results = results;
+                },
+                Err(e) => return MaybeGremlin::Error(e)
+            }
+        }
+
+        match self.results.pop() {
+            Some(g) => MaybeGremlin::Gremlin(g),
+            None => MaybeGremlin::Pull
+        }
     }
 }
 
 
-// // TODO implement something like this
-// pub enum FilterPipeArg {
-//     VertexFilter(VertexFilter),
-//     Closure(Box<dyn Fn(&Vertex) -> bool>)
-// }
-
+pub struct PathPipe;
 
-pub struct FilterPipe<'a> {
-    graph: &'a Graph,
-    filter: VertexFilter
-}
 
+impl Pipe for PathPipe {
 
-impl<'a> FilterPipe<'a> {
-    fn new(graph: &'a Graph, filter: VertexFilter) -> FilterPipe {
-        FilterPipe {
-            graph: graph,
-            filter: filter
+    fn run(self: &mut Self, _graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
+            return MaybeGremlin::Pull
         }
+
+        let mut g = gremlin.unwrap();
+
+        let path = match (&g.path, g.element()) {
+            (Some(p), _) => p.clone(),
+            (None, Some(e)) => vec![e],
+            (None, None) => Vec::new()
+        };
+
+        g.result = Some(Value::Array(path.iter().map(|e| match e { Element::Vertex(id) | Element::Edge(id) => Value::Int(*id as i64) }).collect()));
+
+        return MaybeGremlin::Gremlin ( g )
     }
 }
 
 
-impl<'a> Pipe for FilterPipe<'a> {
-    
-    fn run(self: &mut Self, gremlin: Option<Gremlin>) -> MaybeGremlin {
-        if let Option::None = gremlin {
-            return MaybeGremlin::Pull
-        } 
+pub struct SimplePathPipe;
 
-        let g = gremlin.unwrap();
 
-        if let Option::None = g.vertex {
+impl Pipe for SimplePathPipe {
+
+    fn run(self: &mut Self, _graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        if let Option::None = gremlin {
             return MaybeGremlin::Pull
         }
 
-        let v_id = g.vertex.unwrap();
-        let v = self.graph.get_vertex(&v_id).unwrap();
+        let g = gremlin.unwrap();
 
-        if filter_vertex(v, &self.filter) {
-            return MaybeGremlin::Gremlin ( g.clone() ) 
-        } else {
-            return MaybeGremlin::Pull
+        if let Some(path) = &g.path {
+            let mut seen = HashSet::new();
+
+            if !path.iter().filter(|e| matches!(e, Element::Vertex(_))).all(|e| seen.insert(*e)) {
+                return MaybeGremlin::Pull
+            }
         }
+
+        return MaybeGremlin::Gremlin ( g )
     }
 }
 
 
-pub struct TakePipe {
-    taken: i64,
-    take: i64,
+// hands its gremlin to the rest of a sub-traversal
+struct SeedPipe {
+    gremlin: Option<Gremlin>
 }
 
 
-impl TakePipe {
-    fn new(take: i64) -> TakePipe {
-        TakePipe {
-            taken: 0,
-            take: take
+impl SeedPipe {
+    fn new(gremlin: Gremlin) -> SeedPipe {
+        SeedPipe {
+            gremlin: Some(gremlin)
         }
     }
 }
 
 
-impl<'a> Pipe for TakePipe {
-    
-    fn run(self: &mut Self, gremlin: Option<Gremlin>) -> MaybeGremlin {
-        if self.taken == self.take {
-            self.taken = 0;
-            return  MaybeGremlin::Done
+impl Pipe for SeedPipe {
+
+    fn run(self: &mut Self, _graph: &Graph, _gremlin: Option<Gremlin>) -> MaybeGremlin {
+        match self.gremlin.take() {
+            Some(g) => MaybeGremlin::Gremlin(g),
+            None => MaybeGremlin::Done
         }
+    }
+}
 
-        if let Option::None = gremlin {
-            return MaybeGremlin::Pull
-        } 
 
-        let g = gremlin.unwrap();
+// stands in for a step that couldn't be built
+struct ErrorPipe {
+    error: QueryError
+}
 
-        self.taken += 1;
 
-        return MaybeGremlin::Gremlin ( g.clone() )  
+impl ErrorPipe {
+    fn new(error: QueryError) -> ErrorPipe {
+        ErrorPipe {
+            error: error
+        }
     }
 }
 
 
-pub struct AsPipe {
-    label: String
-}
-
+impl Pipe for ErrorPipe {
 
-impl AsPipe {
-    fn new(label: String) -> AsPipe {
-        AsPipe {
-            label: label
-        }
+    fn run(self: &mut Self, _graph: &Graph, _gremlin: Option<Gremlin>) -> MaybeGremlin {
+        return MaybeGremlin::Error(self.error.clone())
     }
 }
 
 
-impl<'a> Pipe for AsPipe {
-    
-    fn run(self: &mut Self, gremlin: Option<Gremlin>) -> MaybeGremlin {
-        if let Option::None = gremlin {
-            return MaybeGremlin::Pull
-        } 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Element {
+    Vertex(u64),
+    Edge(u64)
+}
 
-        let mut g = gremlin.unwrap();
+#[derive(Debug, Clone)]
+pub struct Gremlin {
+    result: Option<Value>,
+    vertex: Option<u64>,
+    edge: Option<u64>,
+    r#as: Option<HashMap<String, Element>>,
+    path: Option<Vec<Element>>,
+}
 
-        if let Option::None = g.vertex {
-            return MaybeGremlin::Pull
-        }
+impl Gremlin {
+    pub fn new_vertex(id: u64) -> Gremlin {
+        Gremlin::at(Element::Vertex(id), None)
+    }
 
-        let v = g.vertex.unwrap();
+    pub fn new_edge(id: u64) -> Gremlin {
+        Gremlin::at(Element::Edge(id), None)
+    }
 
-        if g.r#as.is_none() { 
-            g.r#as = Some(HashMap::new()); 
+    pub fn new_value(value: Value) -> Gremlin {
+        Gremlin {
+            result: Some(value),
+            vertex: None,
+            edge: None,
+            r#as: None,
+            path: None
         }
+    }
 
-        let p = g.r#as.as_mut().unwrap();
-        
-        p.insert(self.label.clone(), v);
+    /// A gremlin on the vertex `id` that keeps this one's `as` labels.
+    pub fn goto_vertex(self: &Self, id: u64) -> Gremlin {
+        self.moved(Element::Vertex(id))
+    }
 
-        let g = g.clone();
+    /// A gremlin on the edge `id` that keeps this one's `as` labels.
+    pub fn goto_edge(self: &Self, id: u64) -> Gremlin {
+        self.moved(Element::Edge(id))
+    }
 
-        return MaybeGremlin::Gremlin ( g )
+    pub fn vertex(self: &Self) -> Option<u64> {
+        self.vertex
     }
-}
 
+    pub fn edge(self: &Self) -> Option<u64> {
+        self.edge
+    }
 
-pub struct BackPipe {
-    label: String
-}
+    pub fn result(self: &Self) -> Option<&Value> {
+        self.result.as_ref()
+    }
 
+    pub fn set_result(self: &mut Self, result: Option<Value>) {
+        self.result = result;
+    }
 
-impl BackPipe {
-    fn new(label: String) -> BackPipe {
-        BackPipe {
-            label: label
-        }
+    /// The vertices and edges this gremlin went through, if its query tracks
+    /// paths, which it does when it has a `path` or `simple_path` step.
+    pub fn path(self: &Self) -> Option<Vec<QueryResult>> {
+        self.path.as_ref().map(|p| p.iter().map(|e| match e {
+            Element::Vertex(v) => QueryResult::Vertex(*v),
+            Element::Edge(e) => QueryResult::Edge(*e)
+        }).collect())
     }
-}
 
+    /// The vertex or edge recorded under `label` by an `as` step.
+    pub fn labelled(self: &Self, label: &str) -> Option<QueryResult> {
+        match self.r#as.as_ref().and_then(|a| a.get(label)) {
+            Some(Element::Vertex(v)) => Some(QueryResult::Vertex(*v)),
+            Some(Element::Edge(e)) => Some(QueryResult::Edge(*e)),
+            None => None
+        }
+    }
 
-impl Pipe for BackPipe {
-    
-    fn run(self: &mut Self, gremlin: Option<Gremlin>) -> MaybeGremlin {
-        if let Option::None = gremlin {
-            return MaybeGremlin::Pull
-        } 
+    fn at(element: Element, r#as: Option<HashMap<String, Element>>) -> Gremlin {
+        Gremlin {
+            result: None,
+            vertex: match element { Element::Vertex(v) => Some(v), _ => None },
+            edge: match element { Element::Edge(e) => Some(e), _ => None },
+            r#as: r#as,
+            path: None
+        }
+    }
 
-        let g = gremlin.unwrap();
+    fn starting_at(element: Element, track: bool) -> Gremlin {
+        Gremlin {
+            path: if track { Some(vec![element]) } else { None },
+            ..Gremlin::at(element, None)
+        }
+    }
 
-        let a = g.r#as.as_ref().unwrap();
+    // the gremlin one step on, keeping its labels and extending its path
+    // if it keeps one
+    fn moved(self: &Self, element: Element) -> Gremlin {
+        Gremlin {
+            path: self.path.as_ref().map(|p| {
+                let mut p = p.clone();
+                p.push(element);
+                p
+            }),
+            ..Gremlin::at(element, self.r#as.clone())
+        }
+    }
 
-        let v = a.get(&self.label);
+    fn element(self: &Self) -> Option<Element> {
+        match (self.vertex, self.edge) {
+            (Some(v), _) => Some(Element::Vertex(v)),
+            (None, Some(e)) => Some(Element::Edge(e)),
+            _ => None
+        }
+    }
 
-        return MaybeGremlin::Gremlin( Gremlin {
-            vertex: match v { None => None, Some(v) => Some(*v) },
-            r#as: None,
-            result: None
-        })
+    pub fn to_query_result(self: &Self) -> QueryResult {
+        if let Some(result) = &self.result { 
+            QueryResult::Value(result.clone())
+        } else if let Some(vertex) = self.vertex { 
+            QueryResult::Vertex(vertex) 
+        } else if let Some(edge) = self.edge { 
+            QueryResult::Edge(edge) 
+        } else {
+            QueryResult::None
+        }
     }
 }
 
-
-pub struct ExceptPipe {
-    label: String
+#[derive(Debug)]
+pub enum MaybeGremlin {
+    Pull,
+    Done,
+    False,
+    Gremlin(Gremlin),
+    Error(QueryError)
 }
 
+// Where the pipe machine stands between two results. A fresh machine starts
+// at the last pipe and pulls gremlins through the program from there.
+struct Machine {
+    pc: i32,
+    done: i32
+}
 
-impl ExceptPipe {
-    fn new(label: String) -> ExceptPipe {
-        ExceptPipe {
-            label: label
+impl Machine {
+    fn new(program_len: usize) -> Machine {
+        Machine {
+            pc: program_len as i32 - 1,
+            done: -1
         }
     }
-}
 
+    fn next(self: &mut Self, graph: &Graph, program: &mut [Box<dyn Pipe>]) -> Result<Option<Gremlin>, QueryError> {
+
+        let max = program.len() as i32 - 1;
 
-impl<'a> Pipe for ExceptPipe {
+        let mut maybe_gremlin = MaybeGremlin::False;
     
-    fn run(self: &mut Self, gremlin: Option<Gremlin>) -> MaybeGremlin {
-        if let Option::None = gremlin {
-            return MaybeGremlin::Pull
-        } 
+        while self.done < max {
+            
+            let step = &mut program[self.pc as usize];
+            maybe_gremlin = step.run(graph, match maybe_gremlin { MaybeGremlin::Gremlin(g) => Some(g), _ => None });
 
-        let g = gremlin.unwrap();
+            if let MaybeGremlin::Error(e) = maybe_gremlin {
+                return Err(e);
+            }
 
-        let a = g.r#as.as_ref().unwrap();
+            if let MaybeGremlin::Pull = maybe_gremlin {
+                maybe_gremlin = MaybeGremlin::False;
+                if self.pc-1 > self.done {
+                    self.pc -= 1;
+                    continue;
+                }
 
-        let a_v = *a.get(&self.label).unwrap();
+                maybe_gremlin = program[self.pc as usize].finish(graph);
 
-        let v = g.vertex.unwrap();
+                if let MaybeGremlin::Error(e) = maybe_gremlin {
+                    return Err(e);
+                }
 
-        if v == a_v {
-            return MaybeGremlin::Pull
+                if !matches!(maybe_gremlin, MaybeGremlin::Gremlin(_)) {
+                    maybe_gremlin = MaybeGremlin::False;
+                    self.done = self.pc;
+                }
+            }
+
+            if let MaybeGremlin::Done = maybe_gremlin {
+                maybe_gremlin = MaybeGremlin::False;
+                self.done = self.pc;
+            }
+
+            self.pc += 1;
+
+            if self.pc > max {
+                self.pc -= 1;
+                if let MaybeGremlin::Gremlin(r) = maybe_gremlin {
+                    return Ok(Some(r));
+                }
+                maybe_gremlin = MaybeGremlin::False;
+            }
         }
 
-        return MaybeGremlin::Gremlin ( g.clone() )
+        Ok(None)
     }
 }
 
-
-pub struct MergePipe {
-    labels:Vec<String>,
-    vertices:Option<Vec<u64>>
+fn key_value(graph: &Graph, g: &Gremlin, key: &Key) -> Result<Option<Value>, QueryError> {
+    match key {
+        Key::Id => Ok(g.element().map(|e| match e { Element::Vertex(id) | Element::Edge(id) => Value::Int(id as i64) })),
+        Key::Property(p) => Ok(element_property(graph, g, p)?.cloned()),
+        Key::Value => Ok(g.result.clone())
+    }
 }
 
-
-impl MergePipe {
-    fn new(labels:Vec<String>) -> MergePipe {
-        MergePipe {
-            labels: labels,
-            vertices: None
+// Values as JSON written the same way whenever they are equal: object
+// entries sorted and -0.0 as 0.0. JSON has no NaN or infinity, so those are
+// written as strings.
+fn canonical_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Bool(b) => serde_json::Value::Bool(*b),
+        Value::Int(i) => serde_json::Value::from(*i),
+        Value::Float(f) => match serde_json::Number::from_f64(if *f == 0.0 { 0.0 } else { *f }) {
+            Some(n) => serde_json::Value::Number(n),
+            None => serde_json::Value::String(f.to_string())
+        },
+        Value::String(s) => serde_json::Value::String(s.clone()),
+        Value::Array(a) => serde_json::Value::Array(a.iter().map(canonical_json).collect()),
+        Value::Object(o) => {
+            let mut entries: Vec<(&String, &Value)> = o.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            serde_json::Value::Object(entries.into_iter().map(|(k, v)| (k.clone(), canonical_json(v))).collect())
         }
     }
 }
 
-
-impl Pipe for MergePipe {
-    
-    fn run(self: &mut Self, gremlin: Option<Gremlin>) -> MaybeGremlin {
-        if self.vertices.is_none() && gremlin.is_none() {
-            return MaybeGremlin::Pull
+// Whether a program asks for paths, sub-traversals included. Those only
+// exist as closures, so each is built once on a blank query to see its
+// steps; the path has to be kept from the outer query's start, not the
+// branch's.
+fn uses_paths(graph: &Graph, program: &[Box<dyn Pipe>]) -> bool {
+    program.iter().any(|p| {
+        if p.is::<PathPipe>() || p.is::<SimplePathPipe>() {
+            return true
         }
 
-        if self.vertices.is_none() || self.vertices.as_ref().unwrap().is_empty() {
-
-            if gremlin.is_some() {
-                let a = match gremlin.as_ref().unwrap().r#as.as_ref() { None => HashMap::new(), Some(s) => s.clone() };
-                self.vertices = Some(self.labels.iter().filter_map( |id| match a.get(id) { None => None, Some(v) => Some(*v) }).collect());
-            } else {
-                self.vertices = Some(Vec::new());
+        let traversals: Vec<&Traversal> = if let Some(r) = p.downcast_ref::<RepeatPipe>() {
+            vec![&r.steps]
+        } else if let Some(w) = p.downcast_ref::<WherePipe>() {
+            vec![&w.steps]
+        } else if let Some(g) = p.downcast_ref::<GroupPipe>() {
+            g.values.iter().collect()
+        } else if let Some(b) = p.downcast_ref::<BranchPipe>() {
+            match &b.branch {
+                Branch::Union(ts) | Branch::Coalesce(ts) => ts.iter().collect(),
+                Branch::Optional(t) => vec![t],
+                Branch::Choose(test, then, otherwise) => vec![test, then, otherwise]
             }
-        }
-
-        if self.vertices.as_ref().unwrap().is_empty() {
-            return MaybeGremlin::Pull
-        }
-
-        let v = self.vertices.as_mut().unwrap().pop().unwrap();
+        } else {
+            Vec::new()
+        };
 
-        return MaybeGremlin::Gremlin( Gremlin {
-            vertex: Some(v),
-            r#as: if gremlin.is_none() { None } else { gremlin.as_ref().unwrap().r#as.clone() },
-            result: None
+        traversals.into_iter().any(|steps| {
+            let mut sub = Query::seeded(graph, Gremlin::new_value(Value::Null));
+            steps(&mut sub);
+            uses_paths(graph, &sub.program)
         })
-    }
+    })
 }
 
+fn run_traversal(graph: &Graph, g: &Gremlin, steps: &Traversal) -> Result<Vec<Gremlin>, QueryError> {
+    let mut sub = Query::seeded(graph, g.clone());
+    steps(&mut sub);
+    sub.try_run_gremlins()
+}
 
-#[derive(Debug, Clone)]
-pub struct Gremlin {
-    result: Option<Value>,
-    vertex: Option<u64>,
-    r#as: Option<HashMap<String, u64>>,
+// stops at the first result
+fn traversal_yields(graph: &Graph, g: &Gremlin, steps: &Traversal) -> Result<bool, QueryError> {
+    let mut sub = Query::seeded(graph, g.clone());
+    steps(&mut sub);
+    sub.try_iter().next().transpose().map(|r| r.is_some())
 }
 
-#[derive(Debug)]
-pub enum MaybeGremlin {
-    Pull,
-    Done,
-    False,
-    Gremlin(Gremlin)
+// the property of the vertex or edge a gremlin is on
+fn element_property<'g>(graph: &'g Graph, g: &Gremlin, key: &str) -> Result<Option<&'g Value>, QueryError> {
+    match g.element() {
+        None => Ok(None),
+        Some(e) => Ok(element_properties(graph, e)?.get(key))
+    }
+}
+
+fn element_properties(graph: &Graph, element: Element) -> Result<&HashMap<String, Value>, QueryError> {
+    match element {
+        Element::Vertex(id) => Ok(&graph.get_vertex(&id).ok_or(QueryError::VertexNotFound(id))?.properties),
+        Element::Edge(id) => Ok(&graph.edges.get(&id).ok_or(QueryError::EdgeNotFound(id))?.properties)
+    }
 }
 
 fn filter_vertex(vertex:&Vertex, filter:&VertexFilter) -> bool {
@@ -844,6 +3332,10 @@ fn filter_vertex(vertex:&Vertex, filter:&VertexFilter) -> bool {
         VertexFilter::Id(id) => vertex.id == *id,
         VertexFilter::Ids(ids) => ids.contains(&vertex.id),
         VertexFilter::Props(f) => properties_filter(&vertex.properties, f),
+        VertexFilter::Predicates(p) => predicates_filter(&vertex.properties, p),
+        VertexFilter::And(fs) => fs.iter().all(|f| filter_vertex(vertex, f)),
+        VertexFilter::Or(fs) => fs.iter().any(|f| filter_vertex(vertex, f)),
+        VertexFilter::Not(f) => !filter_vertex(vertex, f),
         VertexFilter::Fn(f) => f(vertex)
     }    
 }
@@ -853,7 +3345,13 @@ fn filter_edge(edge:&Edge, filter: &EdgeFilter) -> bool {
         EdgeFilter::None => true,
         EdgeFilter::Label(l) => &edge.label == l,
         EdgeFilter::Labels(v) => v.contains(&edge.label),
-        EdgeFilter::Props(f) => properties_filter(&edge.properties, f)
+        EdgeFilter::Props(f) => properties_filter(&edge.properties, f),
+        EdgeFilter::LabelProps(l, f) => &edge.label == l && properties_filter(&edge.properties, f),
+        EdgeFilter::Predicates(p) => predicates_filter(&edge.properties, p),
+        EdgeFilter::And(fs) => fs.iter().all(|f| filter_edge(edge, f)),
+        EdgeFilter::Or(fs) => fs.iter().any(|f| filter_edge(edge, f)),
+        EdgeFilter::Not(f) => !filter_edge(edge, f),
+        EdgeFilter::Fn(f) => f(edge)
     }
 }
 
@@ -865,4 +3363,131 @@ fn properties_filter(p: &HashMap<String, Value>, f: &HashMap<String, Value>) ->
     }
     
     true
-}
\ No newline at end of file
+}
+
+fn predicates_filter(p: &HashMap<String, Value>, f: &HashMap<String, Predicate>) -> bool {
+    f.iter().all(|(k, pred)| pred.test(p.get(k)))
+}
+
+// Int and Float compare numerically, everything else only within its own type
+fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Int(_), Value::Float(_)) | (Value::Float(_), Value::Int(_)) => compare_values(a, b) == Some(Ordering::Equal),
+        _ => a == b
+    }
+}
+
+fn compare_values(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Int(x), Value::Int(y)) => Some(x.cmp(y)),
+        (Value::Float(x), Value::Float(y)) => x.partial_cmp(y),
+        (Value::Int(x), Value::Float(y)) => compare_int_float(*x, *y),
+        (Value::Float(x), Value::Int(y)) => compare_int_float(*y, *x).map(Ordering::reverse),
+        (Value::String(x), Value::String(y)) => Some(x.cmp(y)),
+        (Value::Bool(x), Value::Bool(y)) => Some(x.cmp(y)),
+        _ => None
+    }
+}
+
+// exact, unlike casting the i64 to f64 which loses precision past 2^53
+fn compare_int_float(i: i64, f: f64) -> Option<Ordering> {
+    if f.is_nan() {
+        return None
+    }
+    if f >= 9223372036854775808.0 {
+        return Some(Ordering::Less)
+    }
+    if f < -9223372036854775808.0 {
+        return Some(Ordering::Greater)
+    }
+
+    let whole = f.trunc();
+
+    match i.cmp(&(whole as i64)) {
+        Ordering::Equal => 0.0.partial_cmp(&(f - whole)),
+        o => Some(o)
+    }
+}
+
+// filter steps straight after a vertex step become part of its search, which
+// lets the index planner see them. Id and Ids searches are left alone since
+// they report missing vertices rather than skipping them, and closures stay
+// in their own step so they are only called on the vertices actually pulled.
+fn fold_vertex_filters(program: &mut Program) {
+    let mut i = 0;
+
+    while i + 1 < program.len() {
+        let foldable = match program[i].downcast_ref::<VertexPipe>() {
+            Some(v) => !matches!(v.filter, VertexFilter::Id(_) | VertexFilter::Ids(_)) && !has_closure(&v.filter)
+                && program[i + 1].downcast_ref::<FilterPipe>().is_some_and(|f| !has_closure(&f.filter)),
+            None => false
+        };
+
+        if !foldable {
+            i += 1;
+            continue
+        }
+
+        let next: Box<dyn Any> = program.remove(i + 1);
+        let filter = next.downcast::<FilterPipe>().unwrap().filter;
+        let v = program[i].downcast_mut::<VertexPipe>().unwrap();
+
+        v.filter = match std::mem::replace(&mut v.filter, VertexFilter::None) {
+            VertexFilter::None => match filter {
+                VertexFilter::Id(_) | VertexFilter::Ids(_) => VertexFilter::And(vec![filter]),
+                f => f
+            },
+            VertexFilter::And(mut fs) => {
+                fs.push(filter);
+                VertexFilter::And(fs)
+            },
+            f => VertexFilter::And(vec![f, filter])
+        };
+    }
+}
+
+fn has_closure(filter: &VertexFilter) -> bool {
+    match filter {
+        VertexFilter::Fn(_) => true,
+        VertexFilter::And(fs) | VertexFilter::Or(fs) => fs.iter().any(has_closure),
+        VertexFilter::Not(f) => has_closure(f),
+        _ => false
+    }
+}
+
+// take(a).take(b) is take(min(a, b)), a negative take being unlimited
+fn merge_takes(program: &mut Program) {
+    let mut i = 0;
+
+    while i + 1 < program.len() {
+        let take = match (program[i].downcast_ref::<TakePipe>(), program[i + 1].downcast_ref::<TakePipe>()) {
+            (Some(a), Some(b)) => if a.take < 0 { b.take } else if b.take < 0 { a.take } else { a.take.min(b.take) },
+            _ => {
+                i += 1;
+                continue
+            }
+        };
+
+        program.remove(i + 1);
+        program[i].downcast_mut::<TakePipe>().unwrap().take = take;
+    }
+}
+
+fn as_float(v: &Value) -> f64 {
+    match v {
+        Value::Int(i) => *i as f64,
+        Value::Float(f) => *f,
+        _ => f64::NAN
+    }
+}
+
+// Int + Int stays Int unless it overflows, anything else is Float
+fn add_numbers(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Int(x), Value::Int(y)) => match x.checked_add(*y) {
+            Some(z) => Value::Int(z),
+            None => Value::Float(*x as f64 + *y as f64)
+        },
+        _ => Value::Float(as_float(a) + as_float(b))
+    }
+}
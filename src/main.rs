@@ -17,7 +17,7 @@ fn main() {
         "type".into() => Value::String("orange".into())
     }).unwrap();
     
-    graph.add_edge(v1, v2, "fruitier".into(), hashmap!{});
+    graph.add_edge(v1, v2, "fruitier".into(), hashmap!{}).unwrap();
     
     let mut q = Query::new(&graph, VertexFilter::Id(v1));
     let out = q.out(EdgeFilter::None).run();
@@ -0,0 +1,43 @@
+// Helpers shared by the integration tests. Not every test file uses all of
+// them.
+#![allow(dead_code)]
+
+use dagoba_rust::*;
+
+pub fn name(n: &str) -> Value {
+    Value::String(n.into())
+}
+
+/// The string results of a query, in the order they came.
+pub fn names(out: Vec<QueryResult>) -> Vec<String> {
+    out.iter().map(|r| match r.as_value() {
+        Value::String(s) => s.clone(),
+        v => panic!("not a name: {:?}", v)
+    }).collect()
+}
+
+pub fn sorted_names(out: Vec<QueryResult>) -> Vec<String> {
+    let mut out = names(out);
+    out.sort();
+    out
+}
+
+/// Vertex results by ascending id.
+pub fn sorted(mut out: Vec<QueryResult>) -> Vec<QueryResult> {
+    out.sort_by_key(|r| *r.as_vertex());
+    out
+}
+
+/// A graph of vertices with just a name, and labelled edges between them
+/// given as (from, to, label) positions in `vertices`.
+pub fn named_graph(vertices: &[&str], edges: &[(usize, usize, &str)]) -> (Graph, Vec<u64>) {
+    let mut graph = Graph::new();
+
+    let ids: Vec<u64> = vertices.iter().map(|n| graph.add_vertex(hashmap!{"name".into() => name(n)}).unwrap()).collect();
+
+    for (from, to, label) in edges {
+        graph.add_edge(ids[*from], ids[*to], (*label).into(), hashmap!{}).unwrap();
+    }
+
+    (graph, ids)
+}
@@ -0,0 +1,82 @@
+#[macro_use] extern crate maplit;
+
+use dagoba_rust::*;
+
+mod common;
+use common::name;
+
+fn build_graph(ages: Vec<Value>) -> Graph {
+    let mut graph = Graph::new();
+
+    for age in ages {
+        graph.add_vertex(hashmap!{"age".into() => age}).unwrap();
+    }
+
+    graph.add_vertex(hashmap!{"name".into() => name("ageless")}).unwrap();
+
+    graph
+}
+
+#[test]
+fn aggregates() {
+    let graph = build_graph(vec![Value::Int(30), Value::Int(12), Value::Float(4.5), name("old")]);
+
+    let all = || Query::new(&graph, VertexFilter::None);
+    let ages = || {
+        let mut q = Query::new(&graph, VertexFilter::None);
+        q.property("age".into());
+        q
+    };
+
+    assert_eq!(all().count().run(), vec![QueryResult::Value(Value::Int(5))]);
+    assert_eq!(ages().count().run(), vec![QueryResult::Value(Value::Int(4))]);
+
+    // Int and Float mix, strings are skipped
+    assert_eq!(ages().sum().run(), vec![QueryResult::Value(Value::Float(46.5))]);
+    assert_eq!(ages().min().run(), vec![QueryResult::Value(Value::Float(4.5))]);
+    assert_eq!(ages().max().run(), vec![QueryResult::Value(Value::Int(30))]);
+    assert_eq!(ages().mean().run(), vec![QueryResult::Value(Value::Float(15.5))]);
+
+    // the result carries on through later steps
+    assert_eq!(all().count().take(1).run(), vec![QueryResult::Value(Value::Int(5))]);
+    assert_eq!(ages().take(2).count().run(), vec![QueryResult::Value(Value::Int(2))]);
+
+    // only one result, however often the query is run
+    let mut q = all();
+    q.count();
+    assert_eq!(q.run().len(), 1);
+    assert_eq!(q.run(), vec![]);
+
+    // vertices have no value to sum
+    assert_eq!(all().sum().run(), vec![QueryResult::Value(Value::Int(0))]);
+    assert_eq!(all().max().run(), vec![]);
+    assert_eq!(all().mean().run(), vec![]);
+
+    assert_eq!(all().filter(VertexFilter::Id(99)).count().run(), vec![QueryResult::Value(Value::Int(0))]);
+}
+
+#[test]
+fn aggregate_numbers() {
+    let graph = build_graph(vec![Value::Int(7), Value::Int(2), Value::Int(3)]);
+
+    let ages = || {
+        let mut q = Query::new(&graph, VertexFilter::None);
+        q.property("age".into());
+        q
+    };
+
+    // all Int stays Int, except for the mean
+    assert_eq!(ages().sum().run(), vec![QueryResult::Value(Value::Int(12))]);
+    assert_eq!(ages().min().run(), vec![QueryResult::Value(Value::Int(2))]);
+    assert_eq!(ages().mean().run(), vec![QueryResult::Value(Value::Float(4.0))]);
+
+    // overflow falls back to Float
+    let graph = build_graph(vec![Value::Int(i64::MAX), Value::Int(1)]);
+    let out = Query::new(&graph, VertexFilter::None).property("age".into()).sum().run();
+    assert_eq!(out, vec![QueryResult::Value(Value::Float(i64::MAX as f64 + 1.0))]);
+
+    // NaN doesn't win min or max
+    let graph = build_graph(vec![Value::Float(f64::NAN), Value::Int(1), Value::Float(f64::NAN)]);
+    let out = Query::new(&graph, VertexFilter::None).property("age".into()).max().run();
+    assert_eq!(out, vec![QueryResult::Value(Value::Int(1))]);
+}
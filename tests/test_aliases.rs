@@ -0,0 +1,55 @@
+#[macro_use] extern crate maplit;
+
+use dagoba_rust::*;
+
+mod common;
+use common::name;
+
+#[test]
+fn aliases() {
+    let mut graph = Graph::new();
+
+    let thor = graph.add_vertex(hashmap!{"name".into() => name("Thor")}).unwrap();
+    let odin = graph.add_vertex(hashmap!{"name".into() => name("Odin")}).unwrap();
+    let jord = graph.add_vertex(hashmap!{"name".into() => name("Jörð")}).unwrap();
+    let borr = graph.add_vertex(hashmap!{"name".into() => name("Borr")}).unwrap();
+
+    graph.add_edge(thor, odin, "parent".into(), hashmap!{}).unwrap();
+    graph.add_edge(thor, jord, "parent".into(), hashmap!{}).unwrap();
+    graph.add_edge(odin, borr, "parent".into(), hashmap!{}).unwrap();
+
+    graph.add_alias("parents", |q| q.out(EdgeFilter::Label("parent".into())));
+    graph.add_alias("children", |q| q.r#in(EdgeFilter::Label("parent".into())));
+    graph.add_alias("grandparents", |q| q.alias("parents").alias("parents"));
+    graph.add_alias("names", |q| q.property("name".into()));
+    assert!(graph.has_alias("parents"));
+
+    let mut out: Vec<Value> = Query::new(&graph, VertexFilter::Id(thor)).alias("parents").alias("names")
+        .run().iter().map(|r| r.as_value().clone()).collect();
+    out.sort_by_key(|v| format!("{:?}", v));
+    assert_eq!(out, vec![name("Jörð"), name("Odin")]);
+
+    let out = Query::new(&graph, VertexFilter::Id(thor)).alias("grandparents").run();
+    assert_eq!(out, vec![QueryResult::Vertex(borr)]);
+
+    // aliases mix with ordinary steps
+    let out = Query::new(&graph, VertexFilter::Id(borr)).alias("children").alias("children")
+        .filter(VertexFilter::Props(hashmap!{"name".into() => name("Thor")})).run();
+    assert_eq!(out, vec![QueryResult::Vertex(thor)]);
+
+    // redefining an alias replaces it
+    graph.add_alias("parents", |q| q.out(EdgeFilter::None).take(1));
+    let out = Query::new(&graph, VertexFilter::Id(odin)).alias("parents").run();
+    assert_eq!(out, vec![QueryResult::Vertex(borr)]);
+
+    // unknown and recursive aliases fail the query
+    assert!(graph.remove_alias("children"));
+    assert!(!graph.remove_alias("children"));
+
+    let out = Query::new(&graph, VertexFilter::Id(borr)).alias("children").try_run();
+    assert_eq!(out, Err(QueryError::UnknownAlias("children".into())));
+
+    graph.add_alias("ancestors", |q| q.alias("parents").alias("ancestors"));
+    let out = Query::new(&graph, VertexFilter::Id(thor)).alias("ancestors").try_run();
+    assert_eq!(out, Err(QueryError::RecursiveAlias("ancestors".into())));
+}
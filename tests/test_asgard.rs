@@ -104,7 +104,7 @@ fn asgard() {
     ];
 
     for x in &relationships {
-        graph.add_edge(*name_idx.get(x[1]).unwrap(), *name_idx.get(x[0]).unwrap(), "parent".into(), hashmap!{});
+        graph.add_edge(*name_idx.get(x[1]).unwrap(), *name_idx.get(x[0]).unwrap(), "parent".into(), hashmap!{}).unwrap();
     }
 
     assert_eq!(graph.vertices.len(), vanir.len() + aesir.len());
@@ -115,7 +115,7 @@ fn asgard() {
     /////////////////////////////
     
     // g.v('Thor') should be Thor
-    let mut q = Query::new(&graph, VertexFilter::Id(name_idx.get("Thor").unwrap().clone()));
+    let mut q = Query::new(&graph, VertexFilter::Id(*name_idx.get("Thor").unwrap()));
     let out = q.run();
 
     let result: Vec<&Box<Vertex>> = out.iter().filter_map(|r| graph.get_vertex(match r {QueryResult::Vertex(id) => id, _ => &0u64})).collect();
@@ -190,7 +190,7 @@ fn asgard() {
     // property works like a map
     let mut q = Query::new(&graph, VertexFilter::Id(*name_idx.get("Thor").unwrap()));
     let out1:Vec<Value> = q.out(EdgeFilter::Label(String::from("parent"))).out(EdgeFilter::Label(String::from("parent")))
-        .run().iter().map(|r| graph.get_vertex(r.as_vertex()).unwrap().properties.get(&"name".to_string()).unwrap().clone() ).collect();
+        .run().iter().map(|r| graph.get_vertex(r.as_vertex()).unwrap().properties.get("name").unwrap().clone() ).collect();
 
     let mut q = Query::new(&graph, VertexFilter::Id(*name_idx.get("Thor").unwrap()));
     let out2:Vec<Value> = q.out(EdgeFilter::Label(String::from("parent"))).out(EdgeFilter::Label(String::from("parent"))).property("name".to_string())
@@ -202,7 +202,7 @@ fn asgard() {
     let mut q = Query::new(&graph, VertexFilter::Id(*name_idx.get("Thor").unwrap()));
     let out = q.out(EdgeFilter::None).r#in(EdgeFilter::None).unique().filter(VertexFilter::Props(hashmap!{"survives".to_string() => Value::Bool(true)})).run();
 
-    assert!(out.len() == 0);
+    assert!(out.is_empty());
 
     // g.v('Thor').out().in().unique().filter({gender: 'male'}) should contain Thor and his sibling
     let mut q = Query::new(&graph, VertexFilter::Id(*name_idx.get("Thor").unwrap()));
@@ -233,13 +233,23 @@ fn asgard() {
     assert!(out.contains(&QueryResult::Vertex(*name_idx.get("Baldr").unwrap())));
     assert!(out.contains(&QueryResult::Vertex(*name_idx.get("Thor").unwrap())));
 
-    // Asynchronous queries should work
+    // Asynchronous queries should work, a page at a time
+    let mut q = Query::new(&graph, VertexFilter::Id(*name_idx.get("Auðumbla").unwrap()));
+    q.r#in(EdgeFilter::None).r#in(EdgeFilter::None).r#in(EdgeFilter::None).property("name".to_string());
+
+    let (out, cursor) = q.run_page(1);
+    assert_eq!(out, vec![ QueryResult::Value( Value::String( "Vé".to_string() )) ]);
+    let (out, cursor) = cursor.unwrap().run_page(1);
+    assert_eq!(out, vec![ QueryResult::Value( Value::String( "Vili".to_string() )) ]);
+    let (out, cursor) = cursor.unwrap().run_page(1);
+    assert_eq!(out, vec![ QueryResult::Value( Value::String( "Odin".to_string() )) ]);
+    assert!(cursor.is_none());
+
+    // and a query cut short by take stays finished
     let mut q = Query::new(&graph, VertexFilter::Id(*name_idx.get("Auðumbla").unwrap()));
     q.r#in(EdgeFilter::None).r#in(EdgeFilter::None).r#in(EdgeFilter::None).property("name".to_string()).take(1);
 
     assert_eq!(q.run(), vec![ QueryResult::Value( Value::String( "Vé".to_string() )) ]);
-    assert_eq!(q.run(), vec![ QueryResult::Value( Value::String( "Vili".to_string() )) ]);
-    assert_eq!(q.run(), vec![ QueryResult::Value( Value::String( "Odin".to_string() )) ]);
     assert_eq!(q.run().len(), 0);
     assert_eq!(q.run().len(), 0);
 
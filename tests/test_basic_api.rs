@@ -28,7 +28,7 @@ fn simple_graph() {
     assert_eq!(graph.edges.len(), 0);
 
     // should add an edge v1->v2
-    graph.add_edge(v1, v2, "fruitier".into(), hashmap!{});
+    graph.add_edge(v1, v2, "fruitier".into(), hashmap!{}).unwrap();
 
     assert_eq!(graph.vertices.len(), 2);
     assert_eq!(graph.edges.len(), 1);
@@ -65,7 +65,7 @@ fn bigger_graph() {
     // should build the graph
     let mut graph = Graph::new();
 
-    let vertices = vec![
+    let vertices = [
         hashmap!{"name" => "Fred"},
         hashmap!{"name" => "Bob"},
         hashmap!{"name" => "Tom"},
@@ -78,23 +78,23 @@ fn bigger_graph() {
         "name".into() => Value::String(h.get("name").unwrap().to_string())
     }).unwrap()).collect();
 
-    graph.add_edge(v_ids[0], v_ids[1], "son".into(), hashmap!{});
-    graph.add_edge(v_ids[1], v_ids[2], "son".into(), hashmap!{});
-    graph.add_edge(v_ids[1], v_ids[3], "son".into(), hashmap!{});
-    graph.add_edge(v_ids[1], v_ids[4], "son".into(), hashmap!{});
-    graph.add_edge(v_ids[1], v_ids[5], "daughter".into(), hashmap!{});
-    graph.add_edge(v_ids[2], v_ids[3], "brother".into(), hashmap!{});
-    graph.add_edge(v_ids[3], v_ids[4], "brother".into(), hashmap!{});
-    graph.add_edge(v_ids[4], v_ids[2], "brother".into(), hashmap!{});
-    graph.add_edge(v_ids[2], v_ids[4], "brother".into(), hashmap!{});
-    graph.add_edge(v_ids[3], v_ids[2], "brother".into(), hashmap!{});
-    graph.add_edge(v_ids[4], v_ids[3], "brother".into(), hashmap!{});
-    graph.add_edge(v_ids[2], v_ids[5], "sister".into(), hashmap!{});
-    graph.add_edge(v_ids[3], v_ids[5], "sister".into(), hashmap!{});
-    graph.add_edge(v_ids[4], v_ids[5], "sister".into(), hashmap!{});
-    graph.add_edge(v_ids[5], v_ids[2], "brother".into(), hashmap!{});
-    graph.add_edge(v_ids[5], v_ids[3], "brother".into(), hashmap!{});
-    graph.add_edge(v_ids[5], v_ids[4], "brother".into(), hashmap!{});
+    graph.add_edge(v_ids[0], v_ids[1], "son".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[1], v_ids[2], "son".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[1], v_ids[3], "son".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[1], v_ids[4], "son".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[1], v_ids[5], "daughter".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[2], v_ids[3], "brother".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[3], v_ids[4], "brother".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[4], v_ids[2], "brother".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[2], v_ids[4], "brother".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[3], v_ids[2], "brother".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[4], v_ids[3], "brother".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[2], v_ids[5], "sister".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[3], v_ids[5], "sister".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[4], v_ids[5], "sister".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[5], v_ids[2], "brother".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[5], v_ids[3], "brother".into(), hashmap!{}).unwrap();
+    graph.add_edge(v_ids[5], v_ids[4], "brother".into(), hashmap!{}).unwrap();
 
     assert_eq!(graph.vertices.len(), 6);
     assert_eq!(graph.edges.len(), 17);
@@ -150,4 +150,230 @@ fn bigger_graph() {
     assert_eq!(out, vec![
         QueryResult::Vertex(v_ids[0])
     ]);
-}
\ No newline at end of file
+}
+
+#[test]
+fn graph_errors() {
+    let mut graph = Graph::new();
+
+    let v1 = graph.add_vertex(hashmap!{}).unwrap();
+    let v2 = graph.add_vertex(hashmap!{}).unwrap();
+    let e1 = graph.add_edge(v1, v2, "knows".into(), hashmap!{}).unwrap();
+
+    assert!(matches!(graph.remove_vertex(99), Err(DagobaError::VertexNotFound(99))));
+    assert!(matches!(graph.remove_edge(99), Err(DagobaError::EdgeNotFound(99))));
+    assert!(matches!(graph.get_out_edges(&99), Err(DagobaError::VertexNotFound(99))));
+    assert!(matches!(graph.get_in_edges(&99), Err(DagobaError::VertexNotFound(99))));
+
+    assert_eq!(graph.get_out_edges(&v1).unwrap().len(), 1);
+    assert_eq!(graph.get_in_edges(&v1).unwrap().len(), 0);
+
+    // an edge whose vertex forgot about it can't be unlinked cleanly
+    graph.vertices.get_mut(&v2).unwrap().e_in.clear();
+    assert!(matches!(graph.remove_edge(e1), Err(DagobaError::DanglingEdge(id)) if id == e1));
+
+    // errors are displayable and usable as std errors
+    let err: Box<dyn std::error::Error> = Box::new(DagobaError::VertexNotFound(99));
+    assert_eq!(err.to_string(), "vertex 99 not found");
+}
+
+
+#[test]
+fn add_edge_is_atomic() {
+    let mut graph = Graph::new();
+
+    let v1 = graph.add_vertex(hashmap!{}).unwrap();
+    let v2 = graph.add_vertex(hashmap!{}).unwrap();
+    let autoid = graph.autoid;
+
+    // missing in-vertex
+    assert!(matches!(graph.add_edge(v1, 99, "x".into(), hashmap!{}), Err(DagobaError::VertexNotFound(99))));
+    // missing out-vertex
+    assert!(matches!(graph.add_edge(99, v2, "x".into(), hashmap!{}), Err(DagobaError::VertexNotFound(99))));
+    // both missing
+    assert!(matches!(graph.add_edge(98, 99, "x".into(), hashmap!{}), Err(DagobaError::VertexNotFound(98))));
+
+    // nothing was linked and no id was burned
+    assert_eq!(graph.autoid, autoid);
+    assert_eq!(graph.edges.len(), 0);
+    for v in graph.vertices.values() {
+        assert!(v.e_in.is_empty());
+        assert!(v.e_out.is_empty());
+    }
+
+    let e1 = graph.add_edge(v1, v2, "x".into(), hashmap!{}).unwrap();
+    assert_eq!(e1, autoid + 1);
+    assert_eq!(graph.vertices[&v1].e_out, vec![e1]);
+    assert_eq!(graph.vertices[&v2].e_in, vec![e1]);
+
+    // a batch with one bad edge adds nothing
+    let batch = vec![
+        (v1, v2, "y".to_string(), hashmap!{}),
+        (v2, 99, "y".to_string(), hashmap!{}),
+    ];
+    assert!(matches!(graph.add_edges(batch), Err(DagobaError::VertexNotFound(99))));
+    assert_eq!(graph.autoid, e1);
+    assert_eq!(graph.edges.len(), 1);
+    assert_eq!(graph.vertices[&v1].e_out, vec![e1]);
+    assert_eq!(graph.vertices[&v2].e_in, vec![e1]);
+
+    // a good batch adds everything, ids in input order
+    let batch = vec![
+        (v1, v2, "y".to_string(), hashmap!{}),
+        (v2, v1, "z".to_string(), hashmap!{}),
+    ];
+    let ids = graph.add_edges(batch).unwrap();
+    assert_eq!(ids, vec![e1 + 1, e1 + 2]);
+    assert_eq!(graph.edges.len(), 3);
+    assert_eq!(graph.edges[&ids[1]].label, "z");
+    assert_eq!(graph.vertices[&v1].e_out, vec![e1, ids[0]]);
+    assert_eq!(graph.vertices[&v1].e_in, vec![ids[1]]);
+
+    assert!(graph.add_edges(vec![]).unwrap().is_empty());
+}
+
+
+#[test]
+fn property_updates() {
+    let mut graph = Graph::new();
+    graph.create_index("name");
+
+    let v1 = graph.add_vertex(hashmap!{"name".into() => Value::String("Fred".into())}).unwrap();
+    let v2 = graph.add_vertex(hashmap!{}).unwrap();
+    let e1 = graph.add_edge(v1, v2, "son".into(), hashmap!{}).unwrap();
+
+    let by_name = |graph: &Graph, name: &str| {
+        Query::new(graph, VertexFilter::Props(hashmap!{"name".into() => Value::String(name.into())})).run()
+    };
+
+    // set returns the previous value and moves the vertex in the index
+    let old = graph.set_vertex_property(v1, "name".into(), Value::String("Bob".into())).unwrap();
+    assert_eq!(old, Some(Value::String("Fred".into())));
+    assert_eq!(by_name(&graph, "Fred"), vec![]);
+    assert_eq!(by_name(&graph, "Bob"), vec![QueryResult::Vertex(v1)]);
+
+    let old = graph.set_vertex_property(v2, "name".into(), Value::String("Tom".into())).unwrap();
+    assert_eq!(old, None);
+    assert_eq!(by_name(&graph, "Tom"), vec![QueryResult::Vertex(v2)]);
+
+    // setting the same value keeps the vertex indexed
+    graph.set_vertex_property(v2, "name".into(), Value::String("Tom".into())).unwrap();
+    assert_eq!(by_name(&graph, "Tom"), vec![QueryResult::Vertex(v2)]);
+
+    let old = graph.remove_vertex_property(v2, "name").unwrap();
+    assert_eq!(old, Some(Value::String("Tom".into())));
+    assert_eq!(by_name(&graph, "Tom"), vec![]);
+    assert_eq!(graph.remove_vertex_property(v2, "name").unwrap(), None);
+
+    // merge reports only the overwritten keys
+    let old = graph.merge_vertex_properties(v1, hashmap!{
+        "name".into() => Value::String("Dick".into()),
+        "age".into() => Value::Int(40)
+    }).unwrap();
+    assert_eq!(old, hashmap!{"name".into() => Value::String("Bob".into())});
+    assert_eq!(graph.get_vertex(&v1).unwrap().properties["age"], Value::Int(40));
+    assert_eq!(by_name(&graph, "Dick"), vec![QueryResult::Vertex(v1)]);
+    assert_eq!(by_name(&graph, "Bob"), vec![]);
+
+    // edges
+    assert_eq!(graph.set_edge_property(e1, "since".into(), Value::Int(1990)).unwrap(), None);
+    assert_eq!(graph.set_edge_property(e1, "since".into(), Value::Int(1991)).unwrap(), Some(Value::Int(1990)));
+    let old = graph.merge_edge_properties(e1, hashmap!{"since".into() => Value::Int(1992), "weight".into() => Value::Float(0.5)}).unwrap();
+    assert_eq!(old, hashmap!{"since".into() => Value::Int(1991)});
+    assert_eq!(graph.remove_edge_property(e1, "weight").unwrap(), Some(Value::Float(0.5)));
+    assert_eq!(graph.edges[&e1].properties, hashmap!{"since".into() => Value::Int(1992)});
+
+    // missing ids
+    assert!(matches!(graph.set_vertex_property(99, "x".into(), Value::Null), Err(DagobaError::VertexNotFound(99))));
+    assert!(matches!(graph.remove_vertex_property(99, "x"), Err(DagobaError::VertexNotFound(99))));
+    assert!(matches!(graph.merge_vertex_properties(99, hashmap!{}), Err(DagobaError::VertexNotFound(99))));
+    assert!(matches!(graph.set_edge_property(99, "x".into(), Value::Null), Err(DagobaError::EdgeNotFound(99))));
+    assert!(matches!(graph.remove_edge_property(99, "x"), Err(DagobaError::EdgeNotFound(99))));
+    assert!(matches!(graph.merge_edge_properties(99, hashmap!{}), Err(DagobaError::EdgeNotFound(99))));
+}
+
+
+#[test]
+fn query_errors() {
+    let mut graph = Graph::new();
+
+    let v1 = graph.add_vertex(hashmap!{"name".into() => Value::String("Fred".into())}).unwrap();
+    let v2 = graph.add_vertex(hashmap!{"name".into() => Value::String("Bob".into())}).unwrap();
+    graph.add_edge(v1, v2, "son".into(), hashmap!{}).unwrap();
+
+    let out = Query::new(&graph, VertexFilter::Id(v1)).out(EdgeFilter::None).try_run();
+    assert_eq!(out, Ok(vec![QueryResult::Vertex(v2)]));
+
+    // unknown vertex ids
+    let out = Query::new(&graph, VertexFilter::Id(99)).try_run();
+    assert_eq!(out, Err(QueryError::VertexNotFound(99)));
+
+    let out = Query::new(&graph, VertexFilter::Ids(vec![v1, 99])).out(EdgeFilter::None).try_run();
+    assert_eq!(out, Err(QueryError::VertexNotFound(99)));
+
+    // missing as labels
+    let out = Query::new(&graph, VertexFilter::Id(v1)).out(EdgeFilter::None).back("nope".into()).try_run();
+    assert_eq!(out, Err(QueryError::MissingLabel("nope".into())));
+
+    let out = Query::new(&graph, VertexFilter::Id(v1)).r#as("me".into()).out(EdgeFilter::None).except("nope".into()).try_run();
+    assert_eq!(out, Err(QueryError::MissingLabel("nope".into())));
+
+    let out = Query::new(&graph, VertexFilter::Id(v1)).r#as("me".into()).out(EdgeFilter::None).back("me".into()).property("name".into()).try_run();
+    assert_eq!(out, Ok(vec![QueryResult::Value(Value::String("Fred".into()))]));
+
+    // query errors convert into graph errors
+    let err: DagobaError = QueryError::VertexNotFound(99).into();
+    assert_eq!(err.to_string(), "query error: vertex 99 not found");
+}
+
+
+#[test]
+fn lazy_iteration() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let mut graph = Graph::new();
+
+    for i in 0..100 {
+        graph.add_vertex(hashmap!{"n".into() => Value::Int(i)}).unwrap();
+    }
+
+    // count how many vertices the filter step actually looks at
+    let seen = Rc::new(Cell::new(0));
+    let counter = seen.clone();
+
+    let mut q = Query::new(&graph, VertexFilter::None);
+    q.filter(VertexFilter::Fn(Box::new(move |_| { counter.set(counter.get() + 1); true })));
+
+    let first: Vec<QueryResult> = q.iter().take(3).collect();
+    assert_eq!(first.len(), 3);
+    assert_eq!(seen.get(), 3);
+
+    // early break in a for loop
+    let mut n = 0;
+    for r in &mut q {
+        assert!(matches!(r, QueryResult::Vertex(_)));
+        n += 1;
+        if n == 2 {
+            break;
+        }
+    }
+    assert_eq!(seen.get(), 5);
+
+    // the rest of the stream is still there
+    assert_eq!(q.iter().count(), 95);
+    assert_eq!(seen.get(), 100);
+
+    // a stream cut short by take doesn't start over on the next run
+    let mut q = Query::new(&graph, VertexFilter::None);
+    q.take(2);
+
+    assert_eq!(q.run().len(), 2);
+    assert_eq!(q.run(), vec![]);
+    assert_eq!(q.iter().count(), 0);
+
+    // try_iter yields the error and then stops
+    let mut q = Query::new(&graph, VertexFilter::Id(999));
+    let out: Vec<Result<QueryResult, QueryError>> = q.try_iter().collect();
+    assert_eq!(out, vec![Err(QueryError::VertexNotFound(999))]);
+}
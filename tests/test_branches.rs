@@ -0,0 +1,89 @@
+#[macro_use] extern crate maplit;
+
+use dagoba_rust::*;
+
+mod common;
+use common::{names, named_graph};
+
+fn build_graph() -> (Graph, Vec<u64>) {
+    named_graph(
+        &["Thor", "Odin", "Jörð", "Borr", "Sif", "Thrud"],
+        &[(0, 1, "parent"), (0, 2, "parent"), (1, 3, "parent"), (0, 4, "spouse"), (5, 0, "parent")])
+}
+
+fn parents<'q, 'g>(q: &'q mut Query<'g>) -> &'q mut Query<'g> {
+    q.out(EdgeFilter::Label("parent".into()))
+}
+
+#[test]
+fn union_steps() {
+    let (mut graph, ids) = build_graph();
+    graph.set_ordered(true);
+
+    let thor = || Query::new(&graph, VertexFilter::Id(ids[0]));
+
+    // branch results come out in branch order
+    let out = names(thor().union(vec![
+        traversal(|q| q.out(EdgeFilter::Label("spouse".into()))),
+        traversal(parents),
+        traversal(|q| q.r#in(EdgeFilter::Label("parent".into())))
+    ]).property("name".into()).run());
+    assert_eq!(out, vec!["Sif", "Odin", "Jörð", "Thrud"]);
+
+    // each incoming gremlin runs every branch
+    let out = Query::new(&graph, VertexFilter::Ids(vec![ids[0], ids[1]]))
+        .union(vec![traversal(parents), traversal(parents)]).count().run();
+    assert_eq!(out, vec![QueryResult::Value(Value::Int(6))]);
+
+    let out = thor().union(vec![]).run();
+    assert_eq!(out, vec![]);
+
+    // branches see the labels set before them
+    let out = thor().r#as("me".into()).union(vec![traversal(|q| parents(q).back("me".into()))]).run();
+    assert_eq!(out, vec![QueryResult::Vertex(ids[0]), QueryResult::Vertex(ids[0])]);
+
+    let out = thor().union(vec![traversal(|q| q.back("nope".into()))]).try_run();
+    assert_eq!(out, Err(QueryError::MissingLabel("nope".into())));
+}
+
+#[test]
+fn coalesce_and_optional_steps() {
+    let (graph, ids) = build_graph();
+
+    let spouse_or_parents = || vec![
+        traversal(|q| q.out(EdgeFilter::Label("spouse".into()))),
+        traversal(parents)
+    ];
+
+    let out = names(Query::new(&graph, VertexFilter::Id(ids[0])).coalesce(spouse_or_parents()).property("name".into()).run());
+    assert_eq!(out, vec!["Sif"]);
+
+    let out = names(Query::new(&graph, VertexFilter::Id(ids[1])).coalesce(spouse_or_parents()).property("name".into()).run());
+    assert_eq!(out, vec!["Borr"]);
+
+    let out = Query::new(&graph, VertexFilter::Id(ids[3])).coalesce(spouse_or_parents()).run();
+    assert_eq!(out, vec![]);
+
+    // gremlins with nowhere to go stay where they are
+    let out = names(Query::new(&graph, VertexFilter::Ids(vec![ids[1], ids[3]])).optional(parents).property("name".into()).run());
+    assert_eq!(out, vec!["Borr", "Borr"]);
+}
+
+#[test]
+fn choose_steps() {
+    let (graph, ids) = build_graph();
+
+    // parents of the married, children of everyone else
+    let mut out = names(Query::new(&graph, VertexFilter::Ids(vec![ids[0], ids[1]]))
+        .choose(
+            |q| q.out(EdgeFilter::Label("spouse".into())),
+            parents,
+            |q| q.r#in(EdgeFilter::Label("parent".into())))
+        .property("name".into()).run());
+    out.sort();
+    assert_eq!(out, vec!["Jörð", "Odin", "Thor"]);
+
+    let out = Query::new(&graph, VertexFilter::Id(ids[0]))
+        .choose(|q| q.back("nope".into()), parents, parents).try_run();
+    assert_eq!(out, Err(QueryError::MissingLabel("nope".into())));
+}
@@ -0,0 +1,138 @@
+#[macro_use] extern crate maplit;
+
+use dagoba_rust::*;
+
+mod common;
+use common::name;
+
+// replaces each vertex with its number of outgoing edges
+struct OutDegree;
+
+impl Pipe for OutDegree {
+    fn run(&mut self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        let mut g = match gremlin {
+            Some(g) => g,
+            None => return MaybeGremlin::Pull
+        };
+
+        let v = match g.vertex() {
+            Some(v) => v,
+            None => return MaybeGremlin::False
+        };
+
+        match graph.get_out_edges(&v) {
+            Ok(edges) => g.set_result(Some(Value::Int(edges.len() as i64))),
+            Err(e) => return MaybeGremlin::Error(QueryError::Pipe(e.to_string()))
+        }
+
+        MaybeGremlin::Gremlin(g)
+    }
+}
+
+// follows an edge by hand, keeping the as labels
+struct FirstOut;
+
+impl Pipe for FirstOut {
+    fn run(&mut self, graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+        let g = match gremlin {
+            Some(g) => g,
+            None => return MaybeGremlin::Pull
+        };
+
+        match graph.get_out_edges(&g.vertex().unwrap()).unwrap().iter().map(|e| e.v_in).min() {
+            Some(v) => MaybeGremlin::Gremlin(g.goto_vertex(v)),
+            None => MaybeGremlin::False
+        }
+    }
+}
+
+// produces the given vertices without any input
+struct Seed(Vec<u64>);
+
+impl Pipe for Seed {
+    fn run(&mut self, _graph: &Graph, _gremlin: Option<Gremlin>) -> MaybeGremlin {
+        match self.0.pop() {
+            Some(v) => MaybeGremlin::Gremlin(Gremlin::new_vertex(v)),
+            None => MaybeGremlin::Done
+        }
+    }
+}
+
+#[test]
+fn custom_pipes() {
+    let mut graph = Graph::new();
+
+    let fred = graph.add_vertex(hashmap!{"name".into() => name("Fred")}).unwrap();
+    let bob = graph.add_vertex(hashmap!{"name".into() => name("Bob")}).unwrap();
+    let tom = graph.add_vertex(hashmap!{"name".into() => name("Tom")}).unwrap();
+
+    graph.add_edge(fred, bob, "son".into(), hashmap!{}).unwrap();
+    graph.add_edge(fred, tom, "son".into(), hashmap!{}).unwrap();
+    graph.add_edge(bob, tom, "brother".into(), hashmap!{}).unwrap();
+
+    let out = Query::new(&graph, VertexFilter::Id(fred)).pipe(Box::new(OutDegree)).run();
+    assert_eq!(out, vec![QueryResult::Value(Value::Int(2))]);
+
+    // custom steps sit between built-in ones and keep the as labels
+    let out = Query::new(&graph, VertexFilter::Id(fred)).r#as("dad".into())
+        .pipe(Box::new(FirstOut)).property("name".into()).run();
+    assert_eq!(out, vec![QueryResult::Value(name("Bob"))]);
+
+    let out = Query::new(&graph, VertexFilter::Id(fred)).r#as("dad".into())
+        .pipe(Box::new(FirstOut)).pipe(Box::new(FirstOut)).back("dad".into()).run();
+    assert_eq!(out, vec![QueryResult::Vertex(fred)]);
+
+    // a step can start a query of its own
+    let mut q = Query::new(&graph, VertexFilter::Ids(vec![]));
+    q.pipe(Box::new(Seed(vec![tom, bob]))).pipe(Box::new(OutDegree));
+    assert_eq!(q.run(), vec![QueryResult::Value(Value::Int(1)), QueryResult::Value(Value::Int(0))]);
+
+    // and report errors
+    let mut q = Query::new(&graph, VertexFilter::Ids(vec![]));
+    q.pipe(Box::new(Seed(vec![99]))).pipe(Box::new(OutDegree));
+    assert_eq!(q.try_run(), Err(QueryError::Pipe("vertex 99 not found".into())));
+
+    // aliases give custom steps a name
+    graph.add_alias("degree", |q| q.pipe(Box::new(OutDegree)));
+    let out = Query::new(&graph, VertexFilter::Id(bob)).alias("degree").run();
+    assert_eq!(out, vec![QueryResult::Value(Value::Int(1))]);
+}
+
+#[test]
+fn gremlin_accessors() {
+    let mut g = Gremlin::new_vertex(4);
+    assert_eq!(g.vertex(), Some(4));
+    assert_eq!(g.edge(), None);
+    assert_eq!(g.result(), None);
+    assert_eq!(g.to_query_result(), QueryResult::Vertex(4));
+
+    g.set_result(Some(Value::Int(7)));
+    assert_eq!(g.result(), Some(&Value::Int(7)));
+    assert_eq!(g.to_query_result(), QueryResult::Value(Value::Int(7)));
+
+    let e = Gremlin::new_edge(9);
+    assert_eq!(e.vertex(), None);
+    assert_eq!(e.to_query_result(), QueryResult::Edge(9));
+
+    // labels set by as steps are visible to custom pipes
+    struct Check;
+
+    impl Pipe for Check {
+        fn run(&mut self, _graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+            match gremlin {
+                None => MaybeGremlin::Pull,
+                Some(g) => {
+                    assert_eq!(g.labelled("start"), Some(QueryResult::Vertex(g.vertex().unwrap())));
+                    assert_eq!(g.labelled("nope"), None);
+                    assert_eq!(g.goto_edge(1).labelled("start"), g.labelled("start"));
+                    MaybeGremlin::Gremlin(g)
+                }
+            }
+        }
+    }
+
+    let mut graph = Graph::new();
+    let v = graph.add_vertex(hashmap!{}).unwrap();
+    let out = Query::new(&graph, VertexFilter::Id(v)).r#as("start".into()).pipe(Box::new(Check)).run();
+    assert_eq!(out, vec![QueryResult::Vertex(v)]);
+}
@@ -0,0 +1,94 @@
+#[macro_use] extern crate maplit;
+
+use dagoba_rust::*;
+
+mod common;
+use common::name;
+
+fn build_graph() -> (Graph, Vec<u64>) {
+    let mut graph = Graph::new();
+
+    let mut ids = Vec::new();
+    for (n, gender) in &[("Thor", "male"), ("Odin", "male"), ("Jörð", "female"), ("Baldr", "male"), ("Frigg", "female")] {
+        ids.push(graph.add_vertex(hashmap!{"name".into() => name(n), "gender".into() => name(gender)}).unwrap());
+    }
+    ids.push(graph.add_vertex(hashmap!{"name".into() => name("Ymir")}).unwrap());
+
+    for (child, parent) in &[(0, 1), (0, 2), (3, 1), (3, 4)] {
+        graph.add_edge(ids[*child], ids[*parent], "parent".into(), hashmap!{}).unwrap();
+    }
+
+    (graph, ids)
+}
+
+#[test]
+fn dedup_values() {
+    let (graph, ids) = build_graph();
+
+    let out = Query::new(&graph, VertexFilter::None).property("gender".into()).dedup().run();
+    assert_eq!(out.len(), 2);
+    assert!(out.contains(&QueryResult::Value(name("male"))));
+    assert!(out.contains(&QueryResult::Value(name("female"))));
+
+    let out = Query::new(&graph, VertexFilter::Ids(vec![ids[0], ids[3]]))
+        .out(EdgeFilter::None).property("name".into()).dedup().run();
+    assert_eq!(out.len(), 3);
+
+    // unique only knows about vertices, so it keeps a value per vertex
+    let out = Query::new(&graph, VertexFilter::None).property("gender".into()).unique().run();
+    assert_eq!(out.len(), 5);
+
+    let out = Query::new(&graph, VertexFilter::None).out_e(EdgeFilter::None).label().dedup().run();
+    assert_eq!(out, vec![QueryResult::Value(name("parent"))]);
+}
+
+#[test]
+fn dedup_elements() {
+    let (graph, ids) = build_graph();
+
+    let out = Query::new(&graph, VertexFilter::Ids(vec![ids[0], ids[3]])).out(EdgeFilter::None).dedup().run();
+    assert_eq!(out.len(), 3);
+
+    // each edge is reached twice, once from each end
+    let out = Query::new(&graph, VertexFilter::None).both_e(EdgeFilter::None).run();
+    assert_eq!(out.len(), 8);
+
+    let out = Query::new(&graph, VertexFilter::None).both_e(EdgeFilter::None).dedup().run();
+    assert_eq!(out.len(), 4);
+    assert!(out.iter().all(|r| matches!(r, QueryResult::Edge(_))));
+}
+
+#[test]
+fn dedup_by_key() {
+    let (graph, ids) = build_graph();
+
+    // one vertex per gender, and one for those without
+    let out = Query::new(&graph, VertexFilter::None).dedup_by(Key::Property("gender".into())).run();
+    assert_eq!(out.len(), 3);
+    assert!(out.contains(&QueryResult::Vertex(ids[5])));
+
+    let out = Query::new(&graph, VertexFilter::Ids(vec![ids[0], ids[3]])).out(EdgeFilter::None)
+        .dedup_by(Key::Id).run();
+    assert_eq!(out.len(), 3);
+
+    let out = Query::new(&graph, VertexFilter::None).property("gender".into())
+        .dedup_by(Key::Value).count().run();
+    assert_eq!(out, vec![QueryResult::Value(Value::Int(2))]);
+}
+
+#[test]
+fn dedup_equality() {
+    let mut graph = Graph::new();
+
+    for v in vec![Value::Int(1), Value::Float(1.0), Value::Float(0.0), Value::Float(-0.0), Value::Float(f64::NAN), Value::Float(f64::NAN),
+                  Value::Array(vec![Value::Int(1)]), Value::Array(vec![Value::Int(1)]),
+                  Value::Object(hashmap!{"a".into() => Value::Int(1), "b".into() => Value::Null}),
+                  Value::Object(hashmap!{"b".into() => Value::Null, "a".into() => Value::Int(1)})] {
+        graph.add_vertex(hashmap!{"v".into() => v}).unwrap();
+    }
+
+    // Int and Float stay apart as they do under ==, zeros are one value and
+    // NaN, equal to nothing, is never dropped
+    let out = Query::new(&graph, VertexFilter::None).property("v".into()).dedup().run();
+    assert_eq!(out.len(), 7);
+}
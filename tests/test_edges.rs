@@ -0,0 +1,115 @@
+#[macro_use] extern crate maplit;
+
+use dagoba_rust::*;
+
+mod common;
+use common::name;
+
+#[test]
+fn edge_steps() {
+    let mut graph = Graph::new();
+
+    let alice = graph.add_vertex(hashmap!{"name".into() => name("Alice")}).unwrap();
+    let bob = graph.add_vertex(hashmap!{"name".into() => name("Bob")}).unwrap();
+    let carol = graph.add_vertex(hashmap!{"name".into() => name("Carol")}).unwrap();
+
+    let ab = graph.add_edge(alice, bob, "knows".into(), hashmap!{"weight".into() => Value::Float(0.5)}).unwrap();
+    let ac = graph.add_edge(alice, carol, "knows".into(), hashmap!{"weight".into() => Value::Float(0.8)}).unwrap();
+    let cb = graph.add_edge(carol, bob, "likes".into(), hashmap!{}).unwrap();
+
+    // out_e / in_e / both_e stop on the edges
+    let mut out = Query::new(&graph, VertexFilter::Id(alice)).out_e(EdgeFilter::None).run();
+    out.sort_by_key(|r| *r.as_edge());
+    assert_eq!(out, vec![QueryResult::Edge(ab), QueryResult::Edge(ac)]);
+
+    let out = Query::new(&graph, VertexFilter::Id(bob)).in_e(EdgeFilter::Label("likes".into())).run();
+    assert_eq!(out, vec![QueryResult::Edge(cb)]);
+
+    let out = Query::new(&graph, VertexFilter::Id(carol)).both_e(EdgeFilter::None).run();
+    assert_eq!(out.len(), 2);
+    assert!(out.contains(&QueryResult::Edge(ac)));
+    assert!(out.contains(&QueryResult::Edge(cb)));
+
+    // in_v / out_v step back onto vertices
+    let out = Query::new(&graph, VertexFilter::Id(carol)).in_e(EdgeFilter::None).out_v().run();
+    assert_eq!(out, vec![QueryResult::Vertex(alice)]);
+
+    let out = Query::new(&graph, VertexFilter::Id(carol)).out_e(EdgeFilter::None).in_v().property("name".into()).run();
+    assert_eq!(out, vec![QueryResult::Value(name("Bob"))]);
+
+    // label and property read the edge
+    let mut out: Vec<Value> = Query::new(&graph, VertexFilter::Id(bob)).in_e(EdgeFilter::None).label()
+        .run().iter().map(|r| r.as_value().clone()).collect();
+    out.sort_by_key(|v| format!("{:?}", v));
+    assert_eq!(out, vec![name("knows"), name("likes")]);
+
+    // what weight is on the edge between Alice and Bob?
+    let out = Query::new(&graph, VertexFilter::Id(alice))
+        .out_e(EdgeFilter::Label("knows".into())).r#as("e".into())
+        .in_v().filter(VertexFilter::Props(hashmap!{"name".into() => name("Bob")}))
+        .back("e".into()).property("weight".into()).run();
+    assert_eq!(out, vec![QueryResult::Value(Value::Float(0.5))]);
+
+    // edges without the property are dropped
+    let out = Query::new(&graph, VertexFilter::Id(bob)).in_e(EdgeFilter::None).property("weight".into()).run();
+    assert_eq!(out, vec![QueryResult::Value(Value::Float(0.5))]);
+
+    // edge filters
+    let out = Query::new(&graph, VertexFilter::Id(alice)).out_e(EdgeFilter::None)
+        .filter_edges(EdgeFilter::Props(hashmap!{"weight".into() => Value::Float(0.8)})).run();
+    assert_eq!(out, vec![QueryResult::Edge(ac)]);
+
+    // vertex filters don't let edges through and vice versa
+    let out = Query::new(&graph, VertexFilter::Id(alice)).out_e(EdgeFilter::None).filter(VertexFilter::None).run();
+    assert_eq!(out, vec![]);
+
+    let out = Query::new(&graph, VertexFilter::Id(alice)).filter_edges(EdgeFilter::None).run();
+    assert_eq!(out, vec![]);
+
+    // except compares edges too
+    let out = Query::new(&graph, VertexFilter::Id(alice)).out_e(EdgeFilter::Label("knows".into())).r#as("e".into())
+        .out_v().out_e(EdgeFilter::None).except("e".into()).run();
+    assert_eq!(out.len(), 2);
+    assert!(!out.iter().all(|r| r == &out[0]));
+}
+
+
+#[test]
+fn edge_filters() {
+    let mut graph = Graph::new();
+
+    let alice = graph.add_vertex(hashmap!{"name".into() => name("Alice")}).unwrap();
+    let bob = graph.add_vertex(hashmap!{"name".into() => name("Bob")}).unwrap();
+    let carol = graph.add_vertex(hashmap!{"name".into() => name("Carol")}).unwrap();
+    let dave = graph.add_vertex(hashmap!{"name".into() => name("Dave")}).unwrap();
+
+    graph.add_edge(alice, bob, "knows".into(), hashmap!{"since".into() => Value::Int(1990), "met".into() => name("school")}).unwrap();
+    graph.add_edge(alice, carol, "knows".into(), hashmap!{"since".into() => Value::Int(2005), "met".into() => name("work")}).unwrap();
+    graph.add_edge(alice, dave, "likes".into(), hashmap!{"since".into() => Value::Int(2010), "met".into() => name("work")}).unwrap();
+
+    let since = |from: i64, to: i64| EdgeFilter::Fn(Box::new(move |e: &Edge| match e.properties.get("since") {
+        Some(Value::Int(y)) => *y >= from && *y < to,
+        _ => false
+    }));
+
+    // closures drive in, out and both
+    let out = Query::new(&graph, VertexFilter::Id(alice)).out(since(2000, 2020)).run();
+    assert_eq!(out.len(), 2);
+    assert!(out.contains(&QueryResult::Vertex(carol)));
+    assert!(out.contains(&QueryResult::Vertex(dave)));
+
+    let out = Query::new(&graph, VertexFilter::Id(bob)).r#in(since(1980, 2000)).run();
+    assert_eq!(out, vec![QueryResult::Vertex(alice)]);
+
+    let out = Query::new(&graph, VertexFilter::Id(carol)).both(since(1980, 2000)).run();
+    assert_eq!(out, vec![]);
+
+    // label and properties together
+    let out = Query::new(&graph, VertexFilter::Id(alice))
+        .out(EdgeFilter::LabelProps("knows".into(), hashmap!{"met".into() => name("work")})).run();
+    assert_eq!(out, vec![QueryResult::Vertex(carol)]);
+
+    let out = Query::new(&graph, VertexFilter::Id(alice))
+        .out_e(EdgeFilter::None).filter_edges(EdgeFilter::LabelProps("likes".into(), hashmap!{"met".into() => name("work")})).in_v().run();
+    assert_eq!(out, vec![QueryResult::Vertex(dave)]);
+}
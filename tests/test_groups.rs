@@ -0,0 +1,137 @@
+#[macro_use] extern crate maplit;
+
+use dagoba_rust::*;
+
+mod common;
+use common::name;
+
+fn object(out: Vec<QueryResult>) -> std::collections::HashMap<String, Value> {
+    assert_eq!(out.len(), 1);
+    match out[0].as_value() {
+        Value::Object(o) => o.clone(),
+        v => panic!("not an object: {:?}", v)
+    }
+}
+
+fn sorted(v: &Value) -> Vec<String> {
+    match v {
+        Value::Array(a) => {
+            let mut a: Vec<String> = a.iter().map(|x| format!("{:?}", x)).collect();
+            a.sort();
+            a
+        },
+        v => panic!("not an array: {:?}", v)
+    }
+}
+
+fn build_graph() -> (Graph, Vec<u64>) {
+    let mut graph = Graph::new();
+
+    let odin = graph.add_vertex(hashmap!{"name".into() => name("Odin"), "species".into() => name("Aesir"), "age".into() => Value::Int(900)}).unwrap();
+    let thor = graph.add_vertex(hashmap!{"name".into() => name("Thor"), "species".into() => name("Aesir"), "age".into() => Value::Int(30)}).unwrap();
+    let baldr = graph.add_vertex(hashmap!{"name".into() => name("Baldr"), "species".into() => name("Aesir"), "age".into() => Value::Int(30)}).unwrap();
+    let freyr = graph.add_vertex(hashmap!{"name".into() => name("Freyr"), "species".into() => name("Vanir")}).unwrap();
+    let njord = graph.add_vertex(hashmap!{"name".into() => name("Njörðr")}).unwrap();
+
+    graph.add_edge(thor, odin, "parent".into(), hashmap!{}).unwrap();
+    graph.add_edge(baldr, odin, "parent".into(), hashmap!{}).unwrap();
+    graph.add_edge(freyr, njord, "parent".into(), hashmap!{}).unwrap();
+
+    (graph, vec![odin, thor, baldr, freyr, njord])
+}
+
+#[test]
+fn group_count() {
+    let (graph, ids) = build_graph();
+
+    let out = object(Query::new(&graph, VertexFilter::None).group_count(Key::Property("species".into())).run());
+    assert_eq!(out, hashmap!{"Aesir".into() => Value::Int(3), "Vanir".into() => Value::Int(1)});
+
+    // other keys are written as JSON
+    let out = object(Query::new(&graph, VertexFilter::None).group_count(Key::Property("age".into())).run());
+    assert_eq!(out, hashmap!{"900".into() => Value::Int(1), "30".into() => Value::Int(2)});
+
+    // how many children does each god have
+    let out = object(Query::new(&graph, VertexFilter::None).out(EdgeFilter::Label("parent".into()))
+        .property("name".into()).group_count(Key::Value).run());
+    assert_eq!(out, hashmap!{"Odin".into() => Value::Int(2), "Njörðr".into() => Value::Int(1)});
+
+    let out = object(Query::new(&graph, VertexFilter::None).out(EdgeFilter::Label("parent".into())).group_count(Key::Id).run());
+    assert_eq!(out, hashmap!{ids[0].to_string() => Value::Int(2), ids[4].to_string() => Value::Int(1)});
+
+    // nothing to group is an empty object
+    let out = object(Query::new(&graph, VertexFilter::Id(ids[0])).out(EdgeFilter::None).group_count(Key::Id).run());
+    assert!(out.is_empty());
+}
+
+#[test]
+fn group_by() {
+    let (graph, ids) = build_graph();
+
+    // vertices grouped by species
+    let out = object(Query::new(&graph, VertexFilter::None).group_by(Key::Property("species".into()), |q| q).run());
+    assert_eq!(out.len(), 2);
+    assert_eq!(sorted(&out["Aesir"]), sorted(&Value::Array(ids[0..3].iter().map(|id| Value::Int(*id as i64)).collect())));
+    assert_eq!(out["Vanir"], Value::Array(vec![Value::Int(ids[3] as i64)]));
+
+    // names of children per parent
+    let out = object(Query::new(&graph, VertexFilter::None).r#as("child".into()).out(EdgeFilter::Label("parent".into()))
+        .group_by(Key::Property("name".into()), |q| q.back("child".into()).property("name".into())).run());
+    assert_eq!(sorted(&out["Odin"]), sorted(&Value::Array(vec![name("Thor"), name("Baldr")])));
+    assert_eq!(out["Njörðr"], Value::Array(vec![name("Freyr")]));
+
+    // members yielding nothing still get their group
+    let out = object(Query::new(&graph, VertexFilter::Ids(vec![ids[0], ids[1]]))
+        .group_by(Key::Property("name".into()), |q| q.out(EdgeFilter::None).property("name".into())).run());
+    assert_eq!(out, hashmap!{"Odin".into() => Value::Array(vec![]), "Thor".into() => Value::Array(vec![name("Odin")])});
+
+    // errors in the sub-traversal fail the query
+    let out = Query::new(&graph, VertexFilter::None).group_by(Key::Id, |q| q.back("nope".into())).try_run();
+    assert_eq!(out, Err(QueryError::MissingLabel("nope".into())));
+}
+
+#[test]
+fn group_key_clashes() {
+    let mut graph = Graph::new();
+
+    for k in [Value::Int(1), Value::String("1".into()), Value::Null, Value::String("null".into())] {
+        graph.add_vertex(hashmap!{"k".into() => k}).unwrap();
+    }
+
+    // values that would be written the same can't share a group
+    let out = Query::new(&graph, VertexFilter::None).group_count(Key::Property("k".into())).try_run();
+    assert!(matches!(out, Err(QueryError::GroupKeyClash(_))));
+
+    let out = Query::new(&graph, VertexFilter::None).group_by(Key::Property("k".into()), |q| q).try_run();
+    assert!(matches!(out, Err(QueryError::GroupKeyClash(_))));
+
+    // equal values still land in one group, Int and Float kept apart
+    let mut graph = Graph::new();
+
+    for k in [Value::Int(1), Value::Float(1.0), Value::Int(1), Value::Float(f64::NAN), Value::Float(f64::NAN)] {
+        graph.add_vertex(hashmap!{"k".into() => k}).unwrap();
+    }
+
+    let out = object(Query::new(&graph, VertexFilter::None).group_count(Key::Property("k".into())).run());
+    assert_eq!(out.len(), 3);
+    assert_eq!(out["1"], Value::Int(2));
+    assert_eq!(out["1.0"], Value::Int(1));
+
+    // however their entries happen to be stored
+    let mut graph = Graph::new();
+
+    for _ in 0..6 {
+        let o = Value::Object(hashmap!{
+            "a".into() => Value::Int(1), "b".into() => Value::Float(-0.0), "c".into() => Value::Null,
+            "d".into() => Value::Array(vec![Value::Object(hashmap!{"x".into() => Value::Int(1), "y".into() => Value::Int(2), "z".into() => Value::Int(3)})])
+        });
+        graph.add_vertex(hashmap!{"o".into() => o}).unwrap();
+    }
+    graph.add_vertex(hashmap!{"o".into() => Value::Object(hashmap!{
+        "a".into() => Value::Int(1), "b".into() => Value::Float(0.0), "c".into() => Value::Null,
+        "d".into() => Value::Array(vec![Value::Object(hashmap!{"z".into() => Value::Int(3), "y".into() => Value::Int(2), "x".into() => Value::Int(1)})])
+    })}).unwrap();
+
+    let out = object(Query::new(&graph, VertexFilter::None).group_count(Key::Property("o".into())).run());
+    assert_eq!(out, hashmap!{r#"{"a":1,"b":0.0,"c":null,"d":[{"x":1,"y":2,"z":3}]}"#.into() => Value::Int(7)});
+}
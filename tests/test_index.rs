@@ -0,0 +1,150 @@
+#[macro_use] extern crate maplit;
+
+use dagoba_rust::*;
+
+mod common;
+use common::{name, sorted};
+
+#[test]
+fn index_lookups() {
+    let mut graph = Graph::new();
+
+    let fred = graph.add_vertex(hashmap!{"name".into() => name("Fred"), "age".into() => Value::Int(40)}).unwrap();
+    let bob = graph.add_vertex(hashmap!{"name".into() => name("Bob"), "age".into() => Value::Int(40)}).unwrap();
+    let tom = graph.add_vertex(hashmap!{"name".into() => name("Tom"), "age".into() => Value::Float(0.0)}).unwrap();
+
+    graph.add_edge(fred, bob, "son".into(), hashmap!{}).unwrap();
+    graph.add_edge(fred, tom, "son".into(), hashmap!{}).unwrap();
+
+    // an index created after the fact picks up existing vertices
+    graph.create_index("name");
+    graph.create_index("age");
+    assert!(graph.has_index("name"));
+    assert!(!graph.has_index("species"));
+
+    let out = Query::new(&graph, VertexFilter::Props(hashmap!{"name".into() => name("Bob")})).run();
+    assert_eq!(out, vec![QueryResult::Vertex(bob)]);
+
+    let out = Query::new(&graph, VertexFilter::Props(hashmap!{"name".into() => name("Nobody")})).run();
+    assert_eq!(out, vec![]);
+
+    // indexed and unindexed keys combine
+    let out = Query::new(&graph, VertexFilter::Props(hashmap!{"age".into() => Value::Int(40), "name".into() => name("Fred")})).run();
+    assert_eq!(out, vec![QueryResult::Vertex(fred)]);
+
+    let out = Query::new(&graph, VertexFilter::Props(hashmap!{"age".into() => Value::Int(40)})).run();
+    assert_eq!(sorted(out), vec![QueryResult::Vertex(fred), QueryResult::Vertex(bob)]);
+
+    // index keys follow Value equality: Int and Float differ, 0.0 matches -0.0
+    let out = Query::new(&graph, VertexFilter::Props(hashmap!{"age".into() => Value::Float(40.0)})).run();
+    assert_eq!(out, vec![]);
+
+    let out = Query::new(&graph, VertexFilter::Props(hashmap!{"age".into() => Value::Float(-0.0)})).run();
+    assert_eq!(out, vec![QueryResult::Vertex(tom)]);
+
+    let out = Query::new(&graph, VertexFilter::Props(hashmap!{"age".into() => Value::Float(f64::NAN)})).run();
+    assert_eq!(out, vec![]);
+
+    // filter steps use the index too
+    let out = Query::new(&graph, VertexFilter::Id(fred)).out(EdgeFilter::None)
+        .filter(VertexFilter::Props(hashmap!{"name".into() => name("Tom")})).run();
+    assert_eq!(out, vec![QueryResult::Vertex(tom)]);
+
+    // dropping an index falls back to scanning
+    assert!(graph.drop_index("age"));
+    assert!(!graph.drop_index("age"));
+
+    let out = Query::new(&graph, VertexFilter::Props(hashmap!{"age".into() => Value::Int(40)})).run();
+    assert_eq!(sorted(out), vec![QueryResult::Vertex(fred), QueryResult::Vertex(bob)]);
+}
+
+#[test]
+fn index_kept_in_sync() {
+    let mut graph = Graph::new();
+    graph.create_index("name");
+
+    let fred = graph.add_vertex(hashmap!{"name".into() => name("Fred")}).unwrap();
+    let bob = graph.add_vertex(hashmap!{"name".into() => name("Bob")}).unwrap();
+    let bob2 = graph.add_vertex(hashmap!{"name".into() => name("Bob")}).unwrap();
+    graph.add_edge(fred, bob, "son".into(), hashmap!{}).unwrap();
+
+    let out = Query::new(&graph, VertexFilter::Props(hashmap!{"name".into() => name("Bob")})).run();
+    assert_eq!(sorted(out), vec![QueryResult::Vertex(bob), QueryResult::Vertex(bob2)]);
+
+    graph.remove_vertex(bob).unwrap();
+
+    let out = Query::new(&graph, VertexFilter::Props(hashmap!{"name".into() => name("Bob")})).run();
+    assert_eq!(out, vec![QueryResult::Vertex(bob2)]);
+
+    graph.remove_vertex(bob2).unwrap();
+
+    let out = Query::new(&graph, VertexFilter::Props(hashmap!{"name".into() => name("Bob")})).run();
+    assert_eq!(out, vec![]);
+
+    // indexes survive a JSON round trip
+    let copy = Graph::from_json(&graph.to_json().unwrap()).unwrap();
+    assert!(copy.has_index("name"));
+
+    let out = Query::new(&copy, VertexFilter::Props(hashmap!{"name".into() => name("Fred")})).run();
+    assert_eq!(out, vec![QueryResult::Vertex(fred)]);
+
+    // changes through the public fields bypass the index, which goes stale
+    // rather than breaking lookups
+    graph.vertices.remove(&fred);
+
+    let out = Query::new(&graph, VertexFilter::Props(hashmap!{"name".into() => name("Fred")})).run();
+    assert_eq!(out, vec![]);
+}
+
+#[test]
+fn composed_filters() {
+    let mut graph = Graph::new();
+    graph.create_index("species");
+
+    let thor = graph.add_vertex(hashmap!{"name".into() => name("Thor"), "species".into() => name("Aesir"), "gender".into() => name("male")}).unwrap();
+    let frigg = graph.add_vertex(hashmap!{"name".into() => name("Frigg"), "species".into() => name("Aesir"), "gender".into() => name("female")}).unwrap();
+    let freya = graph.add_vertex(hashmap!{"name".into() => name("Freya"), "species".into() => name("Vanir"), "gender".into() => name("female")}).unwrap();
+    let ymir = graph.add_vertex(hashmap!{"name".into() => name("Ymir"), "species".into() => name("Giant"), "gender".into() => name("male")}).unwrap();
+
+    graph.add_edge(thor, frigg, "mother".into(), hashmap!{}).unwrap();
+    graph.add_edge(thor, ymir, "enemy".into(), hashmap!{}).unwrap();
+    graph.add_edge(thor, freya, "friend".into(), hashmap!{}).unwrap();
+
+    let aesir = || VertexFilter::Props(hashmap!{"species".into() => name("Aesir")});
+    let female = || VertexFilter::Props(hashmap!{"gender".into() => name("female")});
+
+    let out = Query::new(&graph, VertexFilter::Or(vec![aesir(), female()])).run();
+    assert_eq!(sorted(out), vec![QueryResult::Vertex(thor), QueryResult::Vertex(frigg), QueryResult::Vertex(freya)]);
+
+    let out = Query::new(&graph, VertexFilter::And(vec![aesir(), female()])).run();
+    assert_eq!(out, vec![QueryResult::Vertex(frigg)]);
+
+    let out = Query::new(&graph, VertexFilter::Not(Box::new(VertexFilter::Or(vec![aesir(), female()])))).run();
+    assert_eq!(out, vec![QueryResult::Vertex(ymir)]);
+
+    // ids and indexed branches narrow the search, the rest is still checked
+    let out = Query::new(&graph, VertexFilter::And(vec![
+        VertexFilter::Ids(vec![thor, freya, ymir]),
+        VertexFilter::Not(Box::new(VertexFilter::Props(hashmap!{"name".into() => name("Thor")})))
+    ])).run();
+    assert_eq!(sorted(out), vec![QueryResult::Vertex(freya), QueryResult::Vertex(ymir)]);
+
+    let out = Query::new(&graph, VertexFilter::Or(vec![])).run();
+    assert_eq!(out, vec![]);
+
+    let out = Query::new(&graph, VertexFilter::Id(thor)).out(EdgeFilter::None).filter(VertexFilter::And(vec![female(), aesir()])).run();
+    assert_eq!(out, vec![QueryResult::Vertex(frigg)]);
+
+    // the same for edges
+    let out = Query::new(&graph, VertexFilter::Id(thor))
+        .out(EdgeFilter::Not(Box::new(EdgeFilter::Label("enemy".into())))).run();
+    assert_eq!(sorted(out), vec![QueryResult::Vertex(frigg), QueryResult::Vertex(freya)]);
+
+    let out = Query::new(&graph, VertexFilter::Id(thor))
+        .out(EdgeFilter::Or(vec![EdgeFilter::Label("enemy".into()), EdgeFilter::Label("friend".into())])).run();
+    assert_eq!(sorted(out), vec![QueryResult::Vertex(freya), QueryResult::Vertex(ymir)]);
+
+    let out = Query::new(&graph, VertexFilter::Id(thor))
+        .out(EdgeFilter::And(vec![EdgeFilter::Label("enemy".into()), EdgeFilter::Label("friend".into())])).run();
+    assert_eq!(out, vec![]);
+}
@@ -0,0 +1,122 @@
+#[macro_use] extern crate maplit;
+
+use dagoba_rust::*;
+
+fn build_graph() -> (Graph, Vec<u64>) {
+    let mut graph = Graph::new();
+
+    let v1 = graph.add_vertex(hashmap!{
+        "name".into() => Value::String("Fred".into()),
+        "age".into() => Value::Int(42),
+        "height".into() => Value::Float(1.0),
+        "alive".into() => Value::Bool(true),
+        "nickname".into() => Value::Null,
+        "tags".into() => Value::Array(vec![Value::Int(1), Value::String("two".into()), Value::Float(3.5)]),
+        "address".into() => Value::Object(hashmap!{
+            "city".into() => Value::String("Oslo".into()),
+            "zip".into() => Value::Int(150)
+        })
+    }).unwrap();
+
+    let v2 = graph.add_vertex(hashmap!{"name".into() => Value::String("Bob".into())}).unwrap();
+    let v3 = graph.add_vertex(hashmap!{"name".into() => Value::String("Tom".into())}).unwrap();
+
+    graph.add_edge(v1, v2, "son".into(), hashmap!{"since".into() => Value::Int(1990)}).unwrap();
+    graph.add_edge(v1, v3, "son".into(), hashmap!{}).unwrap();
+    graph.add_edge(v2, v3, "brother".into(), hashmap!{}).unwrap();
+
+    // leave a hole in the id space so autoid is ahead of every stored id
+    let v4 = graph.add_vertex(hashmap!{}).unwrap();
+    graph.remove_vertex(v4).unwrap();
+
+    (graph, vec![v1, v2, v3])
+}
+
+#[test]
+fn json_round_trip() {
+    let (graph, v_ids) = build_graph();
+
+    let json = graph.to_json().unwrap();
+    let mut copy = Graph::from_json(&json).unwrap();
+
+    assert_eq!(copy.autoid, graph.autoid);
+    assert_eq!(copy.vertices.len(), graph.vertices.len());
+    assert_eq!(copy.edges.len(), graph.edges.len());
+
+    for (id, v) in &graph.vertices {
+        let c = copy.get_vertex(id).unwrap();
+        assert_eq!(c.properties, v.properties);
+        assert_eq!(c.e_in, v.e_in);
+        assert_eq!(c.e_out, v.e_out);
+    }
+
+    for (id, e) in &graph.edges {
+        let c = &copy.edges[id];
+        assert_eq!(c.label, e.label);
+        assert_eq!(c.properties, e.properties);
+        assert_eq!(c.v_in, e.v_in);
+        assert_eq!(c.v_out, e.v_out);
+    }
+
+    // Float(1.0) must not come back as Int(1)
+    assert_eq!(copy.get_vertex(&v_ids[0]).unwrap().properties["height"], Value::Float(1.0));
+
+    // queries behave the same on the reloaded graph
+    let out1 = Query::new(&graph, VertexFilter::Id(v_ids[0])).out(EdgeFilter::Label("son".into())).run();
+    let out2 = Query::new(&copy, VertexFilter::Id(v_ids[0])).out(EdgeFilter::Label("son".into())).run();
+
+    assert_eq!(out1, out2);
+
+    // new ids continue after the saved autoid
+    let v5 = copy.add_vertex(hashmap!{}).unwrap();
+    assert_eq!(v5, graph.autoid + 1);
+}
+
+#[test]
+fn json_writer_and_reader() {
+    let (graph, _) = build_graph();
+
+    let mut buffer = Vec::new();
+    graph.to_writer(&mut buffer).unwrap();
+
+    assert_eq!(buffer, graph.to_json().unwrap().into_bytes());
+
+    let copy = Graph::from_reader(buffer.as_slice()).unwrap();
+
+    assert_eq!(copy.autoid, graph.autoid);
+    assert_eq!(copy.vertices.len(), graph.vertices.len());
+    assert_eq!(copy.edges.len(), graph.edges.len());
+
+    for (id, v) in &graph.vertices {
+        assert_eq!(copy.get_vertex(id).unwrap().properties, v.properties);
+    }
+}
+
+#[test]
+fn json_invalid_graphs() {
+    assert!(matches!(Graph::from_json("not json"), Err(DagobaError::Json(_))));
+
+    // edge pointing at a vertex that does not exist
+    let json = r#"{"autoid": 3, "vertices": [{"id": 1}], "edges": [{"id": 2, "label": "x", "v_in": 3, "v_out": 1}]}"#;
+    assert!(matches!(Graph::from_json(json), Err(DagobaError::DanglingEdge(2))));
+
+    // edge reusing a vertex id
+    let json = r#"{"autoid": 3, "vertices": [{"id": 1}, {"id": 2}], "edges": [{"id": 2, "label": "x", "v_in": 1, "v_out": 2}]}"#;
+    assert!(matches!(Graph::from_json(json), Err(DagobaError::DuplicateId(2))));
+
+    // autoid behind the stored ids is bumped so new ids stay unique
+    let json = r#"{"autoid": 0, "vertices": [{"id": 7}], "edges": []}"#;
+    let mut graph = Graph::from_json(json).unwrap();
+    assert_eq!(graph.add_vertex(hashmap!{}).unwrap(), 8);
+}
+
+#[test]
+fn json_non_finite_floats() {
+    for f in &[f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+        let mut graph = Graph::new();
+        graph.add_vertex(hashmap!{"x".into() => Value::Array(vec![Value::Float(*f)])}).unwrap();
+
+        assert!(matches!(graph.to_json(), Err(DagobaError::Json(_))));
+        assert!(matches!(graph.to_writer(Vec::new()), Err(DagobaError::Json(_))));
+    }
+}
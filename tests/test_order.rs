@@ -0,0 +1,118 @@
+#[macro_use] extern crate maplit;
+
+use std::cmp::Ordering;
+
+use dagoba_rust::*;
+
+mod common;
+use common::name;
+
+fn names(out: Vec<QueryResult>) -> Vec<Value> {
+    out.iter().map(|r| r.as_value().clone()).collect()
+}
+
+fn build_graph() -> Graph {
+    let mut graph = Graph::new();
+
+    graph.add_vertex(hashmap!{"name".into() => name("Thor"), "age".into() => Value::Int(30), "species".into() => name("Aesir")}).unwrap();
+    graph.add_vertex(hashmap!{"name".into() => name("Odin"), "age".into() => Value::Float(900.5), "species".into() => name("Aesir")}).unwrap();
+    graph.add_vertex(hashmap!{"name".into() => name("Freyr"), "age".into() => Value::Int(40), "species".into() => name("Vanir")}).unwrap();
+    graph.add_vertex(hashmap!{"name".into() => name("Loki"), "species".into() => name("Giant")}).unwrap();
+    graph.add_vertex(hashmap!{"name".into() => name("Baldr"), "age".into() => Value::Null, "species".into() => name("Aesir")}).unwrap();
+    graph.add_vertex(hashmap!{"name".into() => name("Frigg"), "age".into() => Value::Float(30.0), "species".into() => name("Aesir")}).unwrap();
+
+    graph
+}
+
+#[test]
+fn order_by() {
+    let graph = build_graph();
+
+    let by = |keys: Vec<SortKey>| names(Query::new(&graph, VertexFilter::None).order_by(keys).property("name".into()).run());
+
+    let out = by(vec![SortKey::asc(Key::Property("name".into()))]);
+    assert_eq!(out, vec![name("Baldr"), name("Freyr"), name("Frigg"), name("Loki"), name("Odin"), name("Thor")]);
+
+    let out = by(vec![SortKey::desc(Key::Property("name".into()))]);
+    assert_eq!(out, vec![name("Thor"), name("Odin"), name("Loki"), name("Frigg"), name("Freyr"), name("Baldr")]);
+
+    // Int and Float side by side, missing and null ages last, then by name
+    let out = by(vec![SortKey::asc(Key::Property("age".into())), SortKey::asc(Key::Property("name".into()))]);
+    assert_eq!(out, vec![name("Thor"), name("Frigg"), name("Freyr"), name("Odin"), name("Baldr"), name("Loki")]);
+
+    let out = by(vec![SortKey::desc(Key::Property("age".into())).nulls_first(), SortKey::desc(Key::Property("name".into()))]);
+    assert_eq!(out, vec![name("Loki"), name("Baldr"), name("Odin"), name("Freyr"), name("Frigg"), name("Thor")]);
+
+    // species, then oldest first; an equal Float sorts after an Int, so before it here
+    let out = by(vec![SortKey::asc(Key::Property("species".into())), SortKey::desc(Key::Property("age".into())).nulls_last()]);
+    assert_eq!(out, vec![name("Odin"), name("Frigg"), name("Thor"), name("Baldr"), name("Loki"), name("Freyr")]);
+
+    // ordering values and ids
+    let out = names(Query::new(&graph, VertexFilter::None).property("species".into()).order_by(vec![SortKey::desc(Key::Value)]).take(2).run());
+    assert_eq!(out, vec![name("Vanir"), name("Giant")]);
+
+    let out = Query::new(&graph, VertexFilter::None).order_by(vec![SortKey::asc(Key::Id)]).run();
+    let mut ids: Vec<u64> = graph.vertices.keys().copied().collect();
+    ids.sort();
+    assert_eq!(out, ids.into_iter().map(QueryResult::Vertex).collect::<Vec<_>>());
+}
+
+#[test]
+fn value_total_order() {
+    let values = vec![
+        Value::Null,
+        Value::Bool(false),
+        Value::Bool(true),
+        Value::Float(f64::NEG_INFINITY),
+        Value::Int(-3),
+        Value::Int(2),
+        Value::Float(2.0),
+        Value::Float(2.5),
+        Value::Int(i64::MAX),
+        Value::Float(f64::NAN),
+        name("A"),
+        name("a"),
+        Value::Array(vec![]),
+        Value::Array(vec![Value::Int(1)]),
+        Value::Array(vec![Value::Int(1), Value::Int(0)]),
+        Value::Array(vec![Value::Int(2)]),
+        Value::Object(hashmap!{"a".into() => Value::Int(1)}),
+        Value::Object(hashmap!{"a".into() => Value::Int(1), "b".into() => Value::Int(0)}),
+        Value::Object(hashmap!{"a".into() => Value::Int(2)}),
+    ];
+
+    for (i, a) in values.iter().enumerate() {
+        for (j, b) in values.iter().enumerate() {
+            assert_eq!(a.total_cmp(b), i.cmp(&j), "{:?} vs {:?}", a, b);
+        }
+    }
+
+    assert_eq!(Value::Float(0.0).total_cmp(&Value::Float(-0.0)), Ordering::Equal);
+    assert_eq!(Value::Float(f64::NAN).total_cmp(&Value::Float(f64::NAN)), Ordering::Equal);
+}
+
+#[test]
+fn ordered_graph() {
+    let mut graph = build_graph();
+    graph.set_ordered(true);
+
+    let ids: Vec<u64> = {
+        let mut ids: Vec<u64> = graph.vertices.keys().copied().collect();
+        ids.sort();
+        ids
+    };
+
+    for i in 1..ids.len() {
+        graph.add_edge(ids[0], ids[i], "knows".into(), hashmap!{}).unwrap();
+    }
+    graph.add_edge(ids[3], ids[0], "knows".into(), hashmap!{}).unwrap();
+
+    let out = Query::new(&graph, VertexFilter::None).run();
+    assert_eq!(out, ids.iter().map(|id| QueryResult::Vertex(*id)).collect::<Vec<_>>());
+
+    let out = Query::new(&graph, VertexFilter::Ids(vec![ids[2], ids[0], ids[1]])).run();
+    assert_eq!(out, vec![QueryResult::Vertex(ids[0]), QueryResult::Vertex(ids[1]), QueryResult::Vertex(ids[2])]);
+
+    let out = Query::new(&graph, VertexFilter::Id(ids[0])).both(EdgeFilter::None).run();
+    assert_eq!(out, ids[1..].iter().chain(&ids[3..4]).map(|id| QueryResult::Vertex(*id)).collect::<Vec<_>>());
+}
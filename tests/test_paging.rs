@@ -0,0 +1,81 @@
+#[macro_use] extern crate maplit;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use dagoba_rust::*;
+
+fn build_graph(n: i64) -> (Graph, Vec<QueryResult>) {
+    let mut graph = Graph::new();
+    graph.set_ordered(true);
+
+    let ids = (0..n).map(|i| graph.add_vertex(hashmap!{"n".into() => Value::Int(i)}).unwrap()).collect::<Vec<_>>();
+
+    (graph, ids.into_iter().map(QueryResult::Vertex).collect())
+}
+
+#[test]
+fn skip_and_range() {
+    let (graph, all) = build_graph(10);
+
+    assert_eq!(Query::new(&graph, VertexFilter::None).skip(7).run(), all[7..].to_vec());
+    assert_eq!(Query::new(&graph, VertexFilter::None).skip(20).run(), vec![]);
+    assert_eq!(Query::new(&graph, VertexFilter::None).skip(0).run(), all);
+
+    assert_eq!(Query::new(&graph, VertexFilter::None).range(2, 5).run(), all[2..5].to_vec());
+    assert_eq!(Query::new(&graph, VertexFilter::None).range(8, 15).run(), all[8..].to_vec());
+    assert_eq!(Query::new(&graph, VertexFilter::None).range(5, 2).run(), vec![]);
+
+    // negative bounds count from the start, not the end
+    assert_eq!(Query::new(&graph, VertexFilter::None).skip(-3).run(), all);
+    assert_eq!(Query::new(&graph, VertexFilter::None).range(-3, 2).run(), all[..2].to_vec());
+    assert_eq!(Query::new(&graph, VertexFilter::None).range(-3, -1).run(), vec![]);
+
+    // skipping happens after filtering
+    let out = Query::new(&graph, VertexFilter::None)
+        .filter(VertexFilter::Predicates(hashmap!{"n".into() => Predicate::Gte(Value::Int(4))}))
+        .range(1, 3).run();
+    assert_eq!(out, all[5..7].to_vec());
+}
+
+#[test]
+fn cursors() {
+    let (graph, all) = build_graph(10);
+
+    let (page, cursor) = Query::new(&graph, VertexFilter::None).run_page(4);
+    assert_eq!(page, all[0..4].to_vec());
+
+    let (page, cursor) = cursor.unwrap().run_page(4);
+    assert_eq!(page, all[4..8].to_vec());
+
+    // the last page has no cursor
+    let (page, cursor) = cursor.unwrap().run_page(4);
+    assert_eq!(page, all[8..].to_vec());
+    assert!(cursor.is_none());
+
+    // an exactly full page knows there is nothing after it
+    let (page, cursor) = Query::new(&graph, VertexFilter::None).run_page(10);
+    assert_eq!(page, all);
+    assert!(cursor.is_none());
+
+    let (page, cursor) = Query::new(&graph, VertexFilter::None).run_page(0);
+    assert_eq!(page, vec![]);
+    assert_eq!(cursor.unwrap().run_page(20).0, all);
+
+    // pages don't redo earlier work
+    let calls = Rc::new(Cell::new(0));
+    let c = calls.clone();
+
+    let mut q = Query::new(&graph, VertexFilter::None);
+    q.filter(VertexFilter::Fn(Box::new(move |_| { c.set(c.get() + 1); true })));
+
+    let (_, cursor) = q.run_page(3);
+    assert_eq!(calls.get(), 4);
+    cursor.unwrap().run_page(3);
+    assert_eq!(calls.get(), 7);
+
+    // errors surface from whichever page hits them
+    let mut q = Query::new(&graph, VertexFilter::None);
+    q.skip(5).back("nope".into());
+    assert!(matches!(q.try_run_page(2), Err(QueryError::MissingLabel(_))));
+}
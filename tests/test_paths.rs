@@ -0,0 +1,140 @@
+#[macro_use] extern crate maplit;
+
+use dagoba_rust::*;
+
+fn ids(v: &[u64]) -> QueryResult {
+    QueryResult::Value(Value::Array(v.iter().map(|id| Value::Int(*id as i64)).collect()))
+}
+
+fn build_graph() -> (Graph, Vec<u64>, Vec<u64>) {
+    let mut graph = Graph::new();
+    graph.set_ordered(true);
+
+    let v: Vec<u64> = (0..4).map(|i| graph.add_vertex(hashmap!{"n".into() => Value::Int(i)}).unwrap()).collect();
+
+    let e = vec![
+        graph.add_edge(v[0], v[1], "knows".into(), hashmap!{}).unwrap(),
+        graph.add_edge(v[1], v[2], "knows".into(), hashmap!{}).unwrap(),
+        graph.add_edge(v[2], v[0], "knows".into(), hashmap!{}).unwrap(),
+        graph.add_edge(v[1], v[3], "knows".into(), hashmap!{}).unwrap(),
+    ];
+
+    (graph, v, e)
+}
+
+#[test]
+fn paths() {
+    let (graph, v, e) = build_graph();
+
+    let out = Query::new(&graph, VertexFilter::Id(v[0])).out(EdgeFilter::None).out(EdgeFilter::None).path().run();
+    assert_eq!(out, vec![ids(&[v[0], v[1], v[2]]), ids(&[v[0], v[1], v[3]])]);
+
+    // edge steps put the edges in the path
+    let out = Query::new(&graph, VertexFilter::Id(v[0])).out_e(EdgeFilter::None).in_v().out_e(EdgeFilter::None).path().run();
+    assert_eq!(out, vec![ids(&[v[0], e[0], v[1], e[1]]), ids(&[v[0], e[0], v[1], e[3]])]);
+
+    // back and merge count as steps
+    let out = Query::new(&graph, VertexFilter::Id(v[0])).r#as("start".into()).out(EdgeFilter::None).back("start".into()).path().run();
+    assert_eq!(out, vec![ids(&[v[0], v[1], v[0]])]);
+
+    // a lone vertex
+    let out = Query::new(&graph, VertexFilter::Id(v[3])).path().run();
+    assert_eq!(out, vec![ids(&[v[3]])]);
+
+    // paths carry through sub-traversals
+    let out = Query::new(&graph, VertexFilter::Id(v[0])).repeat(|q| q.out(EdgeFilter::None)).times(2).path().run();
+    assert_eq!(out, vec![ids(&[v[0], v[1], v[2]]), ids(&[v[0], v[1], v[3]])]);
+
+    let out = Query::new(&graph, VertexFilter::Id(v[1])).group_by(Key::Id, |q| q.out(EdgeFilter::None).path()).run();
+    assert_eq!(out, vec![QueryResult::Value(Value::Object(hashmap!{
+        v[1].to_string() => Value::Array(vec![
+            Value::Array(vec![Value::Int(v[1] as i64), Value::Int(v[2] as i64)]),
+            Value::Array(vec![Value::Int(v[1] as i64), Value::Int(v[3] as i64)])
+        ])
+    }))]);
+}
+
+#[test]
+fn simple_paths() {
+    let (graph, v, _) = build_graph();
+
+    // round the triangle and back to the start
+    let out = Query::new(&graph, VertexFilter::Id(v[0])).out(EdgeFilter::None).out(EdgeFilter::None).out(EdgeFilter::None).run();
+    assert_eq!(out, vec![QueryResult::Vertex(v[0])]);
+
+    let out = Query::new(&graph, VertexFilter::Id(v[0])).out(EdgeFilter::None).out(EdgeFilter::None).out(EdgeFilter::None).simple_path().run();
+    assert_eq!(out, vec![]);
+
+    // both goes straight back where it came from
+    let out = Query::new(&graph, VertexFilter::Id(v[3])).both(EdgeFilter::None).both(EdgeFilter::None).simple_path().path().run();
+    assert_eq!(out.len(), 2);
+    assert!(out.contains(&ids(&[v[3], v[1], v[0]])));
+    assert!(out.contains(&ids(&[v[3], v[1], v[2]])));
+
+    // gremlins made by custom steps keep their paths
+    struct Check;
+
+    impl Pipe for Check {
+        fn run(&mut self, _graph: &Graph, gremlin: Option<Gremlin>) -> MaybeGremlin {
+            match gremlin {
+                None => MaybeGremlin::Pull,
+                Some(g) => {
+                    assert_eq!(g.path().unwrap().len(), 2);
+                    MaybeGremlin::Gremlin(g.goto_vertex(42))
+                }
+            }
+        }
+    }
+
+    let out = Query::new(&graph, VertexFilter::Id(v[3])).r#in(EdgeFilter::None).pipe(Box::new(Check)).path().run();
+    assert_eq!(out, vec![ids(&[v[3], v[1], 42])]);
+}
+
+#[test]
+fn paths_in_sub_traversals() {
+    let (graph, v, _) = build_graph();
+
+    let from_start = || Query::new(&graph, VertexFilter::Id(v[0]));
+
+    // a path asked for inside a branch still starts where the query did
+    let out = from_start().out(EdgeFilter::None).union(vec![traversal(|q| q.out(EdgeFilter::None).path())]).run();
+    assert_eq!(out, vec![ids(&[v[0], v[1], v[2]]), ids(&[v[0], v[1], v[3]])]);
+
+    let out = from_start().out(EdgeFilter::None).group_by(Key::Id, |q| q.out(EdgeFilter::None).path()).run();
+    assert_eq!(out, vec![QueryResult::Value(Value::Object(hashmap!{
+        v[1].to_string() => Value::Array(vec![ids(&[v[0], v[1], v[2]]).as_value().clone(), ids(&[v[0], v[1], v[3]]).as_value().clone()])
+    }))]);
+
+    // so simple_path sees the whole walk: v2 leads back to v0, v3 nowhere
+    let out = from_start().out(EdgeFilter::None).out(EdgeFilter::None).not_(|q| q.out(EdgeFilter::None).simple_path()).run();
+    assert_eq!(out, vec![QueryResult::Vertex(v[2]), QueryResult::Vertex(v[3])]);
+
+    // and nested sub-traversals are looked into as well
+    let out = from_start().out(EdgeFilter::None).out(EdgeFilter::None)
+        .optional(|q| q.where_(|q| q.out(EdgeFilter::None).simple_path()).property("n".into())).run();
+    assert_eq!(out, vec![QueryResult::Vertex(v[2]), QueryResult::Vertex(v[3])]);
+}
+
+#[test]
+fn sub_traversals_built_once_per_run() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let (graph, _, _) = build_graph();
+
+    let calls = Rc::new(Cell::new(0));
+    let c = calls.clone();
+
+    // looking for path steps builds each sub-traversal once up front, and
+    // the sub-queries run per gremlin don't look again
+    let out = Query::new(&graph, VertexFilter::None).union(vec![traversal(move |q| {
+        let c = c.clone();
+        q.where_(move |q| {
+            c.set(c.get() + 1);
+            q.out(EdgeFilter::None)
+        })
+    })]).run();
+
+    assert_eq!(out.len(), 3);
+    assert_eq!(calls.get(), 4 + 1);
+}
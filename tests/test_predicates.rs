@@ -0,0 +1,84 @@
+#[macro_use] extern crate maplit;
+
+use dagoba_rust::*;
+
+mod common;
+use common::{name, sorted};
+
+#[test]
+fn predicate_values() {
+    let int = Value::Int(30);
+    let float = Value::Float(30.5);
+
+    // Int and Float compare by value
+    assert!(Predicate::Gt(Value::Float(29.9)).test(Some(&int)));
+    assert!(Predicate::Lt(Value::Int(31)).test(Some(&float)));
+    assert!(Predicate::Eq(Value::Float(30.0)).test(Some(&int)));
+    assert!(Predicate::Gte(Value::Float(30.0)).test(Some(&int)));
+    assert!(!Predicate::Gt(Value::Float(30.0)).test(Some(&int)));
+    assert!(Predicate::In(vec![Value::Float(30.0)]).test(Some(&int)));
+
+    // precision isn't lost on large ints
+    assert!(Predicate::Gt(Value::Float(9007199254740992.0)).test(Some(&Value::Int(9007199254740993))));
+
+    // incomparable values never match
+    assert!(!Predicate::Gt(Value::Int(1)).test(Some(&name("2"))));
+    assert!(!Predicate::Lte(Value::Int(1)).test(Some(&name("0"))));
+    assert!(!Predicate::Gt(Value::Float(f64::NAN)).test(Some(&int)));
+    assert!(Predicate::Ne(name("30")).test(Some(&int)));
+
+    // between includes the lower bound, excludes the upper
+    let between = Predicate::Between(Value::Int(30), Value::Int(40));
+    assert!(between.test(Some(&int)));
+    assert!(between.test(Some(&float)));
+    assert!(!between.test(Some(&Value::Int(40))));
+
+    // strings and arrays
+    assert!(Predicate::StartsWith("Th".into()).test(Some(&name("Thor"))));
+    assert!(Predicate::Contains("ho".into()).test(Some(&name("Thor"))));
+    assert!(!Predicate::Contains("ho".into()).test(Some(&int)));
+    assert!(Predicate::Regex(Regex::new("^T.*r$").unwrap()).test(Some(&name("Thor"))));
+    assert!(Predicate::ArrayContains(Value::Int(2)).test(Some(&Value::Array(vec![Value::Float(2.0)]))));
+    assert!(!Predicate::ArrayContains(Value::Int(2)).test(Some(&int)));
+
+    // missing properties
+    assert!(Predicate::NotExists.test(None));
+    assert!(!Predicate::NotExists.test(Some(&Value::Null)));
+    assert!(Predicate::Exists.test(Some(&Value::Null)));
+    assert!(!Predicate::Exists.test(None));
+    assert!(!Predicate::Ne(Value::Int(1)).test(None));
+}
+
+#[test]
+fn predicate_filters() {
+    let mut graph = Graph::new();
+
+    let fred = graph.add_vertex(hashmap!{"name".into() => name("Fred"), "age".into() => Value::Int(42)}).unwrap();
+    let bob = graph.add_vertex(hashmap!{"name".into() => name("Bob"), "age".into() => Value::Float(18.5)}).unwrap();
+    let tom = graph.add_vertex(hashmap!{"name".into() => name("Tom")}).unwrap();
+
+    graph.add_edge(fred, bob, "son".into(), hashmap!{"since".into() => Value::Int(2001)}).unwrap();
+    graph.add_edge(fred, tom, "son".into(), hashmap!{"since".into() => Value::Int(2010)}).unwrap();
+
+    let out = Query::new(&graph, VertexFilter::Predicates(hashmap!{"age".into() => Predicate::Gt(Value::Int(30))})).run();
+    assert_eq!(out, vec![QueryResult::Vertex(fred)]);
+
+    let out = Query::new(&graph, VertexFilter::Predicates(hashmap!{"age".into() => Predicate::Exists})).run();
+    assert_eq!(sorted(out), vec![QueryResult::Vertex(fred), QueryResult::Vertex(bob)]);
+
+    // every predicate has to hold
+    let out = Query::new(&graph, VertexFilter::Predicates(hashmap!{
+        "age".into() => Predicate::Lt(Value::Int(30)),
+        "name".into() => Predicate::StartsWith("F".into())
+    })).run();
+    assert_eq!(out, vec![]);
+
+    let out = Query::new(&graph, VertexFilter::Id(fred)).out(EdgeFilter::None)
+        .filter(VertexFilter::Predicates(hashmap!{"age".into() => Predicate::NotExists})).run();
+    assert_eq!(out, vec![QueryResult::Vertex(tom)]);
+
+    // edges too
+    let out = Query::new(&graph, VertexFilter::Id(fred))
+        .out(EdgeFilter::Predicates(hashmap!{"since".into() => Predicate::Gte(Value::Int(2005))})).run();
+    assert_eq!(out, vec![QueryResult::Vertex(tom)]);
+}
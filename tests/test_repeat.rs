@@ -0,0 +1,105 @@
+#[macro_use] extern crate maplit;
+
+use dagoba_rust::*;
+
+mod common;
+use common::{name, sorted_names, named_graph};
+
+fn build_graph() -> (Graph, Vec<u64>) {
+    let (mut graph, ids) = named_graph(
+        &["Thor", "Odin", "Jörð", "Borr", "Bestla", "Búri", "Bölþorn"],
+        &[(0, 1, "parent"), (0, 2, "parent"), (1, 3, "parent"), (1, 4, "parent"), (3, 5, "parent"), (4, 6, "parent")]);
+
+    graph.add_alias("parents", |q| q.out(EdgeFilter::Label("parent".into())));
+
+    (graph, ids)
+}
+
+#[test]
+fn repeat_steps() {
+    let (graph, ids) = build_graph();
+
+    let thor = || Query::new(&graph, VertexFilter::Id(ids[0]));
+
+    // grandparents, without spelling out each step
+    let out = sorted_names(thor().repeat(|q| q.alias("parents")).times(2).property("name".into()).run());
+    assert_eq!(out, vec!["Bestla", "Borr"]);
+
+    // all ancestors of Thor
+    let out = sorted_names(thor().repeat(|q| q.alias("parents")).emit().property("name".into()).run());
+    assert_eq!(out, vec!["Bestla", "Borr", "Bölþorn", "Búri", "Jörð", "Odin"]);
+
+    // within two hops
+    let out = sorted_names(thor().repeat(|q| q.alias("parents")).times(2).emit().property("name".into()).run());
+    assert_eq!(out, vec!["Bestla", "Borr", "Jörð", "Odin"]);
+
+    // where the line ends
+    let out = sorted_names(thor().repeat(|q| q.alias("parents")).property("name".into()).run());
+    assert_eq!(out, vec!["Bölþorn", "Búri", "Jörð"]);
+
+    // up to Borr
+    let out = sorted_names(thor().repeat(|q| q.alias("parents"))
+        .until(VertexFilter::Props(hashmap!{"name".into() => name("Borr")})).property("name".into()).run());
+    assert_eq!(out, vec!["Borr"]);
+
+    let out = sorted_names(thor().repeat(|q| q.alias("parents"))
+        .until(VertexFilter::Props(hashmap!{"name".into() => name("Borr")})).emit().property("name".into()).run());
+    assert_eq!(out, vec!["Bestla", "Borr", "Bölþorn", "Jörð", "Odin"]);
+
+    // zero rounds leaves the gremlin where it is
+    let out = thor().repeat(|q| q.alias("parents")).times(0).run();
+    assert_eq!(out, vec![QueryResult::Vertex(ids[0])]);
+
+    // each incoming gremlin gets its own walk
+    let out = Query::new(&graph, VertexFilter::Ids(vec![ids[1], ids[3]])).repeat(|q| q.alias("parents")).times(1).run();
+    assert_eq!(out.len(), 3);
+
+    // the modifiers need a repeat to modify
+    let out = thor().times(2).try_run();
+    assert_eq!(out, Err(QueryError::MisplacedStep("times".into())));
+
+    let out = thor().repeat(|q| q.back("nope".into())).try_run();
+    assert_eq!(out, Err(QueryError::MissingLabel("nope".into())));
+}
+
+#[test]
+fn repeat_cycles() {
+    let mut graph = Graph::new();
+
+    let ids: Vec<u64> = (0..5).map(|i| graph.add_vertex(hashmap!{"n".into() => Value::Int(i)}).unwrap()).collect();
+
+    // a ring with a shortcut back to the start
+    for i in 0..5 {
+        graph.add_edge(ids[i], ids[(i + 1) % 5], "next".into(), hashmap!{}).unwrap();
+    }
+    graph.add_edge(ids[2], ids[0], "next".into(), hashmap!{}).unwrap();
+
+    let out = Query::new(&graph, VertexFilter::Id(ids[0])).repeat(|q| q.out(EdgeFilter::None)).emit().run();
+    assert_eq!(out.len(), 4);
+    assert!(!out.contains(&QueryResult::Vertex(ids[0])));
+
+    // everything reachable within 5 hops, each once
+    let out = Query::new(&graph, VertexFilter::Id(ids[0])).repeat(|q| q.both(EdgeFilter::None)).times(5).emit().run();
+    assert_eq!(out.len(), 4);
+
+    // walking edges visits each edge once as well
+    let out = Query::new(&graph, VertexFilter::Id(ids[0])).repeat(|q| q.out_e(EdgeFilter::None).in_v()).times(10).run();
+    assert_eq!(out, vec![]);
+
+    // going round the ring never reaches an end
+    let out = Query::new(&graph, VertexFilter::Id(ids[0])).repeat(|q| q.out(EdgeFilter::None)).run();
+    assert_eq!(out, vec![]);
+}
+
+#[test]
+fn repeat_joins() {
+    // a diamond a -> b, c -> d with a tail d -> e
+    let (graph, ids) = named_graph(&["a", "b", "c", "d", "e"], &[(0, 1, "x"), (0, 2, "x"), (1, 3, "x"), (2, 3, "x"), (3, 4, "x")]);
+
+    // c's way on was already taken through b, but its walk goes on all the same
+    let out = sorted_names(Query::new(&graph, VertexFilter::Id(ids[0])).repeat(|q| q.out(EdgeFilter::None)).property("name".into()).run());
+    assert_eq!(out, vec!["e"]);
+
+    let out = sorted_names(Query::new(&graph, VertexFilter::Id(ids[0])).repeat(|q| q.out(EdgeFilter::None)).emit().property("name".into()).run());
+    assert_eq!(out, vec!["b", "c", "d", "e"]);
+}
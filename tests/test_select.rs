@@ -0,0 +1,67 @@
+#[macro_use] extern crate maplit;
+
+use dagoba_rust::*;
+
+mod common;
+use common::name;
+
+fn build_graph() -> (Graph, Vec<u64>) {
+    let mut graph = Graph::new();
+    graph.set_ordered(true);
+
+    let odin = graph.add_vertex(hashmap!{"name".into() => name("Odin"), "age".into() => Value::Int(900)}).unwrap();
+    let thor = graph.add_vertex(hashmap!{"name".into() => name("Thor"), "age".into() => Value::Int(30)}).unwrap();
+    let modi = graph.add_vertex(hashmap!{"name".into() => name("Móði")}).unwrap();
+    let magni = graph.add_vertex(hashmap!{"name".into() => name("Magni"), "age".into() => Value::Int(3)}).unwrap();
+
+    graph.add_edge(odin, thor, "child".into(), hashmap!{}).unwrap();
+    graph.add_edge(thor, modi, "child".into(), hashmap!{}).unwrap();
+    graph.add_edge(thor, magni, "child".into(), hashmap!{}).unwrap();
+
+    (graph, vec![odin, thor, modi, magni])
+}
+
+#[test]
+fn select() {
+    let (graph, ids) = build_graph();
+
+    let family = || {
+        let mut q = Query::new(&graph, VertexFilter::Id(ids[0]));
+        q.r#as("parent".into()).out(EdgeFilter::None).r#as("child".into()).out(EdgeFilter::None).r#as("grandchild".into());
+        q
+    };
+
+    let labels = || vec!["parent".to_string(), "child".to_string(), "grandchild".to_string()];
+
+    let out = family().select(labels()).run();
+    assert_eq!(out, vec![
+        QueryResult::Value(Value::Object(hashmap!{"parent".into() => Value::Int(ids[0] as i64), "child".into() => Value::Int(ids[1] as i64), "grandchild".into() => Value::Int(ids[2] as i64)})),
+        QueryResult::Value(Value::Object(hashmap!{"parent".into() => Value::Int(ids[0] as i64), "child".into() => Value::Int(ids[1] as i64), "grandchild".into() => Value::Int(ids[3] as i64)})),
+    ]);
+
+    // one property for every label
+    let out = family().select(labels()).by("name".into()).run();
+    assert_eq!(out, vec![
+        QueryResult::Value(Value::Object(hashmap!{"parent".into() => name("Odin"), "child".into() => name("Thor"), "grandchild".into() => name("Móði")})),
+        QueryResult::Value(Value::Object(hashmap!{"parent".into() => name("Odin"), "child".into() => name("Thor"), "grandchild".into() => name("Magni")})),
+    ]);
+
+    // or taking turns, with Null for what's missing
+    let out = family().select(labels()).by("name".into()).by("age".into()).run();
+    assert_eq!(out, vec![
+        QueryResult::Value(Value::Object(hashmap!{"parent".into() => name("Odin"), "child".into() => Value::Int(30), "grandchild".into() => name("Móði")})),
+        QueryResult::Value(Value::Object(hashmap!{"parent".into() => name("Odin"), "child".into() => Value::Int(30), "grandchild".into() => name("Magni")})),
+    ]);
+
+    // edges can be selected too
+    let out = Query::new(&graph, VertexFilter::Id(ids[0])).out_e(EdgeFilter::None).r#as("e".into())
+        .in_v().r#as("v".into()).select(vec!["e".into(), "v".into()]).run();
+    let e = *graph.get_vertex(&ids[0]).unwrap().e_out.first().unwrap();
+    assert_eq!(out, vec![QueryResult::Value(Value::Object(hashmap!{"e".into() => Value::Int(e as i64), "v".into() => Value::Int(ids[1] as i64)}))]);
+
+    let out = family().select(vec!["parent".into(), "nope".into()]).try_run();
+    assert_eq!(out, Err(QueryError::MissingLabel("nope".into())));
+
+    let out = family().by("name".into()).try_run();
+    assert_eq!(out, Err(QueryError::MisplacedStep("by".into())));
+}
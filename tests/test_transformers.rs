@@ -0,0 +1,119 @@
+#[macro_use] extern crate maplit;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use dagoba_rust::*;
+
+mod common;
+use common::name;
+
+fn build_graph() -> (Graph, Vec<u64>) {
+    let mut graph = Graph::new();
+
+    let fred = graph.add_vertex(hashmap!{"name".into() => name("Fred"), "age".into() => Value::Int(40)}).unwrap();
+    let bob = graph.add_vertex(hashmap!{"name".into() => name("Bob"), "age".into() => Value::Int(12)}).unwrap();
+    let tom = graph.add_vertex(hashmap!{"name".into() => name("Tom"), "age".into() => Value::Int(10)}).unwrap();
+
+    graph.add_edge(fred, bob, "son".into(), hashmap!{}).unwrap();
+    graph.add_edge(fred, tom, "son".into(), hashmap!{}).unwrap();
+
+    (graph, vec![fred, bob, tom])
+}
+
+// records the pipe types each program ends up with
+fn spy(graph: &mut Graph, priority: i32) -> Rc<RefCell<Vec<Vec<&'static str>>>> {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let l = log.clone();
+
+    graph.add_transformer(priority, move |program: &mut Program| {
+        l.borrow_mut().push(program.iter().map(|p| {
+            if p.is::<VertexPipe>() { "vertex" }
+            else if p.is::<FilterPipe>() { "filter" }
+            else if p.is::<TakePipe>() { "take" }
+            else { "other" }
+        }).collect());
+    });
+
+    log
+}
+
+#[test]
+fn builtin_transformers() {
+    let (mut graph, ids) = build_graph();
+    let log = spy(&mut graph, 0);
+
+    // filters after a vertex step are folded into it
+    let out = Query::new(&graph, VertexFilter::None)
+        .filter(VertexFilter::Props(hashmap!{"name".into() => name("Bob")}))
+        .filter(VertexFilter::Predicates(hashmap!{"age".into() => Predicate::Gt(Value::Int(11))}))
+        .run();
+    assert_eq!(out, vec![QueryResult::Vertex(ids[1])]);
+    assert_eq!(log.borrow().last().unwrap(), &vec!["vertex"]);
+
+    // but not into an id lookup, nor when the filter is a closure
+    let out = Query::new(&graph, VertexFilter::Id(ids[0]))
+        .filter(VertexFilter::Props(hashmap!{"name".into() => name("Fred")})).run();
+    assert_eq!(out, vec![QueryResult::Vertex(ids[0])]);
+    assert_eq!(log.borrow().last().unwrap(), &vec!["vertex", "filter"]);
+
+    // a folded id filter still skips missing vertices
+    let out = Query::new(&graph, VertexFilter::None).filter(VertexFilter::Ids(vec![ids[2], 99])).run();
+    assert_eq!(out, vec![QueryResult::Vertex(ids[2])]);
+    assert_eq!(log.borrow().last().unwrap(), &vec!["vertex"]);
+
+    Query::new(&graph, VertexFilter::None).filter(VertexFilter::Fn(Box::new(|_| true))).run();
+    assert_eq!(log.borrow().last().unwrap(), &vec!["vertex", "filter"]);
+
+    // consecutive takes merge
+    let out = Query::new(&graph, VertexFilter::Id(ids[0])).out(EdgeFilter::None).take(2).take(1).run();
+    assert_eq!(out.len(), 1);
+    assert_eq!(log.borrow().last().unwrap(), &vec!["vertex", "other", "take"]);
+
+    let out = Query::new(&graph, VertexFilter::Id(ids[0])).out(EdgeFilter::None).take(-1).take(5).run();
+    assert_eq!(out.len(), 2);
+
+    // the program is only transformed once
+    let n = log.borrow().len();
+    let mut q = Query::new(&graph, VertexFilter::None);
+    q.take(1);
+    q.run();
+    q.run();
+    assert_eq!(log.borrow().len(), n + 1);
+
+    // and not at all once the transformers are cleared
+    graph.clear_transformers();
+    let out = Query::new(&graph, VertexFilter::None)
+        .filter(VertexFilter::Props(hashmap!{"name".into() => name("Tom")})).run();
+    assert_eq!(out, vec![QueryResult::Vertex(ids[2])]);
+}
+
+#[test]
+fn custom_transformers() {
+    let (mut graph, ids) = build_graph();
+
+    let order = Rc::new(RefCell::new(Vec::new()));
+
+    for (priority, label) in [(10, "b"), (200, "a"), (10, "c"), (-5, "d")] {
+        let o = order.clone();
+        graph.add_transformer(priority, move |_: &mut Program| o.borrow_mut().push(label));
+    }
+
+    let before = spy(&mut graph, 150);
+    let after = spy(&mut graph, 50);
+
+    // drop every take step
+    graph.add_transformer(20, |program: &mut Program| program.retain(|p| !p.is::<TakePipe>()));
+
+    let out = Query::new(&graph, VertexFilter::None)
+        .filter(VertexFilter::Props(hashmap!{"name".into() => name("Fred")}))
+        .out(EdgeFilter::None).take(1).run();
+
+    assert_eq!(out.len(), 2);
+    assert!(out.contains(&QueryResult::Vertex(ids[1])));
+    assert!(out.contains(&QueryResult::Vertex(ids[2])));
+
+    assert_eq!(*order.borrow(), vec!["a", "b", "c", "d"]);
+    assert_eq!(before.borrow()[0], vec!["vertex", "filter", "other", "take"]);
+    assert_eq!(after.borrow()[0], vec!["vertex", "other", "take"]);
+}
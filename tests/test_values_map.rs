@@ -0,0 +1,48 @@
+#[macro_use] extern crate maplit;
+
+use dagoba_rust::*;
+
+mod common;
+use common::name;
+
+#[test]
+fn values_map() {
+    let mut graph = Graph::new();
+
+    let thor = graph.add_vertex(hashmap!{"name".into() => name("Thor"), "age".into() => Value::Int(30), "id".into() => name("shadowed?")}).unwrap();
+    let odin = graph.add_vertex(hashmap!{"name".into() => name("Odin")}).unwrap();
+    let e = graph.add_edge(thor, odin, "parent".into(), hashmap!{"since".into() => Value::Int(1)}).unwrap();
+
+    let out = Query::new(&graph, VertexFilter::Id(thor)).values_map(None).run();
+    assert_eq!(out, vec![QueryResult::Value(Value::Object(hashmap!{
+        "id".into() => Value::Int(thor as i64),
+        "properties".into() => Value::Object(hashmap!{"name".into() => name("Thor"), "age".into() => Value::Int(30), "id".into() => name("shadowed?")})
+    }))]);
+
+    // only the keys asked for, missing ones left out
+    let out = Query::new(&graph, VertexFilter::Id(thor)).out(EdgeFilter::None).values_map(Some(vec!["name".into(), "age".into()])).run();
+    assert_eq!(out, vec![QueryResult::Value(Value::Object(hashmap!{
+        "id".into() => Value::Int(odin as i64),
+        "properties".into() => Value::Object(hashmap!{"name".into() => name("Odin")})
+    }))]);
+
+    // edges bring their label and endpoints
+    let out = Query::new(&graph, VertexFilter::Id(thor)).out_e(EdgeFilter::None).values_map(None).run();
+    assert_eq!(out, vec![QueryResult::Value(Value::Object(hashmap!{
+        "id".into() => Value::Int(e as i64),
+        "label".into() => name("parent"),
+        "v_in".into() => Value::Int(odin as i64),
+        "v_out".into() => Value::Int(thor as i64),
+        "properties".into() => Value::Object(hashmap!{"since".into() => Value::Int(1)})
+    }))]);
+
+    // the shape matches the JSON records
+    let record: serde_json::Value = serde_json::from_str(&graph.to_json().unwrap()).unwrap();
+    let out = Query::new(&graph, VertexFilter::Id(odin)).values_map(None).run();
+    let map = serde_json::to_value(out[0].as_value()).unwrap();
+    assert!(record["vertices"].as_array().unwrap().contains(&map));
+
+    // plain values have nothing to map
+    let out = Query::new(&graph, VertexFilter::Id(thor)).count().values_map(None).run();
+    assert_eq!(out, vec![]);
+}
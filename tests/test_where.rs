@@ -0,0 +1,73 @@
+#[macro_use] extern crate maplit;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use dagoba_rust::*;
+
+mod common;
+use common::{name, sorted_names};
+
+fn build_graph() -> Graph {
+    let mut graph = Graph::new();
+
+    let god = |graph: &mut Graph, n: &str, gender: &str| graph.add_vertex(hashmap!{"name".into() => name(n), "gender".into() => name(gender)}).unwrap();
+
+    let odin = god(&mut graph, "Odin", "male");
+    let frigg = god(&mut graph, "Frigg", "female");
+    let thor = god(&mut graph, "Thor", "male");
+    let baldr = god(&mut graph, "Baldr", "male");
+    let sif = god(&mut graph, "Sif", "female");
+    let thrud = god(&mut graph, "Þrúðr", "female");
+    let ymir = god(&mut graph, "Ymir", "male");
+
+    for (parent, child) in &[(odin, thor), (odin, baldr), (frigg, baldr), (thor, thrud), (sif, thrud)] {
+        graph.add_edge(*child, *parent, "parent".into(), hashmap!{}).unwrap();
+    }
+
+    graph.add_alias("children", |q| q.r#in(EdgeFilter::Label("parent".into())));
+
+    // ymir is nobody's parent and has none himself
+    assert!(graph.get_vertex(&ymir).unwrap().e_in.is_empty());
+
+    graph
+}
+
+#[test]
+fn where_and_not() {
+    let graph = build_graph();
+
+    let gods = || Query::new(&graph, VertexFilter::None);
+    let female = || VertexFilter::Props(hashmap!{"gender".into() => name("female")});
+
+    // gods with at least one female child
+    let out = sorted_names(gods().where_(move |q| q.alias("children").filter(female())).property("name".into()).run());
+    assert_eq!(out, vec!["Sif", "Thor"]);
+
+    // vertices with no outgoing parent edge
+    let out = sorted_names(gods().not_(|q| q.out(EdgeFilter::Label("parent".into()))).property("name".into()).run());
+    assert_eq!(out, vec!["Frigg", "Odin", "Sif", "Ymir"]);
+
+    // both together: parents without parents of their own
+    let out = sorted_names(gods().where_(|q| q.alias("children")).not_(|q| q.out(EdgeFilter::Label("parent".into())))
+        .property("name".into()).run());
+    assert_eq!(out, vec!["Frigg", "Odin", "Sif"]);
+
+    // the sub-traversal sees the labels so far
+    let out = sorted_names(gods().r#as("me".into()).where_(|q| q.alias("children").out(EdgeFilter::None).except("me".into()))
+        .property("name".into()).run());
+    assert_eq!(out, vec!["Frigg", "Odin", "Sif", "Thor"]);
+
+    // and stops at its first result
+    let calls = Rc::new(Cell::new(0));
+    let c = calls.clone();
+    gods().filter(VertexFilter::Props(hashmap!{"name".into() => name("Odin")}))
+        .where_(move |q| {
+            let c = c.clone();
+            q.alias("children").filter(VertexFilter::Fn(Box::new(move |_| { c.set(c.get() + 1); true })))
+        }).run();
+    assert_eq!(calls.get(), 1);
+
+    let out = gods().where_(|q| q.back("nope".into())).try_run();
+    assert_eq!(out, Err(QueryError::MissingLabel("nope".into())));
+}